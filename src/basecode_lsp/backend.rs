@@ -1,56 +1,201 @@
+use super::config::*;
+use super::daemon::*;
+use super::document::*;
+use super::encoding::*;
 use super::file::*;
+use super::folding::*;
+use super::inline::*;
+use super::lexer::*;
+use super::link::*;
+use super::marker::*;
+use super::progress::*;
+use super::rename::*;
 use super::snippet::*;
+use super::spell::*;
+use super::symbol::*;
+use super::tags::*;
 use super::tmux::*;
+use super::transport::Listen;
 use super::command::*;
 use super::trie::*;
 use super::util::*;
+use super::workspace::*;
 
 use clap::Parser;
 use hashbrown::HashMap;
+use serde_json::json;
+use serde_json::Value;
 use simple_log::*;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::*;
 
-#[derive(Parser, Debug)]
+// Options left out fall back to the config files, so none of them has a
+// default here; see `Settings::default` for the built-in values.
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct LspArgs {
     #[arg(long)]
     snippet_folder: Option<String>,
     #[arg(long)]
     root_folder: Option<String>,
-    #[arg(long, default_value_t = 2)]
-    min_word_len: usize,
-    #[arg(long, default_value_t = true)]
-    tmux_source: bool,
-    #[arg(long, default_value_t = false)]
-    command_source: bool,
+    #[arg(long)]
+    min_word_len: Option<usize>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    tmux_source: Option<bool>,
+    /// minimum number of seconds between two tmux scrapes
+    #[arg(long)]
+    tmux_interval: Option<u64>,
+    /// milliseconds to wait after the last edit before scraping tmux
+    #[arg(long)]
+    tmux_debounce: Option<u64>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    command_source: Option<bool>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    index_workspace: Option<bool>,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    spell_check: Option<bool>,
+    #[arg(long)]
+    dictionary: Option<String>,
+    /// report TODO, FIXME, HACK and XXX markers as diagnostics
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    marker_check: Option<bool>,
     #[arg(long)]
     pub debug: bool,
+    /// accept connections on tcp:PORT or unix:PATH instead of stdio
+    #[arg(long)]
+    pub listen: Option<Listen>,
+    /// share the word index, tmux words and snippets between connections
+    #[arg(long)]
+    pub shared: bool,
+    /// with --shared, exit after this many seconds without connections
+    #[arg(long, default_value_t = DEFAULT_IDLE_TIMEOUT)]
+    pub idle_timeout: u64,
+    /// forward stdio to the per-user daemon, starting it when needed; the
+    /// daemon keeps the options of the shim that started it
+    #[arg(long)]
+    pub daemon: bool,
+}
+
+impl LspArgs {
+    fn settings_update(&self) -> SettingsUpdate {
+        SettingsUpdate {
+            snippet_folder: self.snippet_folder.clone(),
+            root_folder: self.root_folder.clone(),
+            min_word_len: self.min_word_len,
+            tmux_source: self.tmux_source,
+            tmux_interval: self.tmux_interval,
+            tmux_debounce: self.tmux_debounce,
+            command_source: self.command_source,
+            index_workspace: self.index_workspace,
+            spell_check: self.spell_check,
+            dictionary: self.dictionary.clone(),
+            marker_check: self.marker_check,
+            ..SettingsUpdate::default()
+        }
+    }
 }
 
+const SPELL_SOURCE: &str = "basecode-spell";
+const MARKER_SOURCE: &str = "basecode-marker";
+const ADD_TO_DICTIONARY_COMMAND: &str = "basecode.addToDictionary";
+const RELOAD_SNIPPETS_COMMAND: &str = "basecode.reloadSnippets";
+const REFRESH_TMUX_COMMAND: &str = "basecode.refreshTmux";
+const CLEAR_INDEX_COMMAND: &str = "basecode.clearIndex";
+const REBUILD_INDEX_COMMAND: &str = "basecode.rebuildIndex";
+const INDEX_STATS_COMMAND: &str = "basecode.indexStats";
+const TOGGLE_SOURCE_COMMAND: &str = "basecode.toggleSource";
+const COMMANDS: [&str; 7] = [
+    ADD_TO_DICTIONARY_COMMAND,
+    RELOAD_SNIPPETS_COMMAND,
+    REFRESH_TMUX_COMMAND,
+    CLEAR_INDEX_COMMAND,
+    REBUILD_INDEX_COMMAND,
+    INDEX_STATS_COMMAND,
+    TOGGLE_SOURCE_COMMAND,
+];
+const MAX_SPELL_SUGGESTIONS: usize = 5;
+const SNIPPET_WATCHER_ID: &str = "basecode-snippet-watcher";
+const INDEX_CHUNK_SIZE: usize = 64;
+
 #[derive(Debug)]
 pub struct Backend {
-    documents: Mutex<HashMap<String, String>>,
-    snippets: Mutex<HashMap<String, Vec<Snippet>>>,
-    trie: Mutex<Trie>,
-    tmux_source: Mutex<Vec<String>>,
-    lsp_args: LspArgs,
+    client: Client,
+    documents: Mutex<HashMap<String, Document>>,
+    snippet_stores: SnippetStores,
+    /// The store of the current snippet folders, held so it stays shared.
+    snippets: Mutex<SharedSnippetStore>,
+    snippet_watcher: Mutex<Option<JoinHandle<()>>>,
+    trie: Arc<Mutex<WordIndex>>,
+    session_id: usize,
+    commands: Mutex<Vec<String>>,
+    tags: Mutex<TagIndex>,
+    tmux_source: Arc<Mutex<Vec<String>>>,
+    tmux_trigger: Arc<Notify>,
+    tmux_settings: Arc<Mutex<Settings>>,
+    tmux_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    daemon: bool,
+    workspace_folders: Mutex<Vec<PathBuf>>,
+    workspace_files: Mutex<Vec<PathBuf>>,
+    workspace_lines: Mutex<LineIndex>,
+    symbol_patterns: Mutex<Vec<SymbolPattern>>,
+    dictionary: Mutex<Dictionary>,
+    vocabulary: Mutex<Vocabulary>,
+    settings: Arc<Mutex<Settings>>,
+    config: Mutex<ConfigLayers>,
+    client_capabilities: Mutex<ClientCapabilities>,
+    /// The client's inlineCompletion dynamicRegistration capability, which
+    /// `ClientCapabilities` has no field for.
+    inline_completion_registration: AtomicBool,
+    position_encoding: Mutex<PositionEncoding>,
+    progress: Mutex<HashMap<String, Arc<AtomicBool>>>,
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
-        if let Some(snippet_folder) = self.lsp_args.snippet_folder.clone() {
-            info!("loading snippet folder: {}", snippet_folder);
-            let mut snippets_lock = self.snippets.lock().await;
-            prepare_snippet(snippet_folder, &mut snippets_lock);
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        *self.client_capabilities.lock().await = params.capabilities.clone();
+        let offered_encodings = params.capabilities.general.as_ref().and_then(|g| g.position_encodings.as_ref());
+        let position_encoding = PositionEncoding::negotiate(offered_encodings);
+        *self.position_encoding.lock().await = position_encoding;
+        let mut workspace_folders: Vec<PathBuf> = params
+            .workspace_folders
+            .iter()
+            .flatten()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect();
+        if workspace_folders.is_empty() {
+            workspace_folders.extend(params.root_uri.as_ref().and_then(|uri| uri.to_file_path().ok()));
         }
+        *self.workspace_folders.lock().await = workspace_folders;
+        if let Some(options) = params.initialization_options.as_ref() {
+            match parse_settings_update(options).and_then(validated) {
+                Ok(update) => self.config.lock().await.client = update,
+                Err(e) => {
+                    let message = format!("initializationOptions ignored: {}", e);
+                    self.client.show_message(MessageType::ERROR, message).await;
+                }
+            }
+        }
+        self.load_config_files().await;
+        // invalid patterns were reported with the config files
+        self.compile_symbol_patterns().await;
+
+        self.load_dictionaries().await;
 
         let trigger_characters = Some(vec!["/".to_string(), "\"".to_string(), "'".to_string()]);
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
+                position_encoding: Some(position_encoding.kind()),
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
                     TextDocumentSyncKind::FULL,
                 )),
@@ -59,14 +204,68 @@ impl LanguageServer for Backend {
                     trigger_characters,
                     ..CompletionOptions::default()
                 }),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: COMMANDS.iter().map(|c| c.to_string()).collect(),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
                 ..ServerCapabilities::default()
             },
             ..InitializeResult::default()
         })
     }
 
+    async fn initialized(&self, _: InitializedParams) {
+        let mut tmux_task = self.tmux_task.lock().await;
+        if tmux_task.is_none() {
+            *tmux_task = Some(tokio::spawn(refresh_tmux_words(
+                self.tmux_trigger.clone(),
+                self.tmux_settings.clone(),
+                self.tmux_source.clone(),
+            )));
+        }
+        drop(tmux_task);
+        self.trigger_tmux().await;
+        self.register_inline_completion().await;
+        // loaded after initialize so that progress can be reported; daemon
+        // sessions reuse the snippets an earlier session loaded
+        if !self.daemon || self.snippet_store().await.lock().await.is_empty() {
+            self.reload_snippets().await;
+        }
+        self.watch_snippets().await;
+        self.refresh_commands().await;
+        self.index_workspace().await;
+    }
+
     async fn shutdown(&self) -> Result<()> {
         info!("shutdown basecode-lsp");
+        // the daemon keeps scraping tmux for the other sessions
+        if !self.daemon {
+            if let Some(task) = self.tmux_task.lock().await.take() {
+                task.abort();
+            }
+        }
+        if let Some(task) = self.snippet_watcher.lock().await.take() {
+            task.abort();
+        }
         Ok(())
     }
 
@@ -74,108 +273,1187 @@ impl LanguageServer for Backend {
         let mut document_lock = self.documents.lock().await;
         document_lock.insert(
             params.text_document.uri.to_string(),
-            params.text_document.text.clone(),
+            Document::new(params.text_document.text.clone()),
         );
 
         self.add_words(params.text_document.text.clone(), params.text_document.uri.to_string()).await;
-        self.maybe_update_tmux().await;
+        drop(document_lock);
+        self.trigger_tmux().await;
+        self.publish_diagnostics(params.text_document.uri.as_str()).await;
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         let mut document_lock = self.documents.lock().await;
 
         let uri = params.text_document.uri.to_string();
-        if let Some(content) = document_lock.get(&uri) {
-            self.remove_words(content.clone()).await;
+        if let Some(document) = document_lock.get(&uri) {
+            self.remove_words(document.text().to_string(), &uri).await;
         }
         document_lock.remove(&uri);
-        self.maybe_update_tmux().await;
+        drop(document_lock);
+        self.trigger_tmux().await;
+        if self.settings().await.diagnostics_enabled() {
+            self.client.publish_diagnostics(params.text_document.uri, Vec::new(), None).await;
+        }
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let mut workspace_folders = self.workspace_folders.lock().await;
+        for folder in params.event.removed {
+            if let Ok(path) = folder.uri.to_file_path() {
+                workspace_folders.retain(|f| *f != path);
+            }
+        }
+        for folder in params.event.added {
+            if let Ok(path) = folder.uri.to_file_path() {
+                if !workspace_folders.contains(&path) {
+                    workspace_folders.push(path);
+                }
+            }
+        }
+        drop(workspace_folders);
+        if self.settings().await.index_workspace {
+            self.rebuild_index().await;
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let folders = self.settings().await.all_snippet_folders();
+        let canonical_folders: Vec<PathBuf> =
+            folders.iter().map(|folder| Path::new(folder).canonicalize().unwrap_or(PathBuf::from(folder))).collect();
+        let changed = params.changes.iter().filter_map(|change| change.uri.to_file_path().ok()).any(|path| {
+            let parent = path.parent().and_then(|parent| parent.canonicalize().ok());
+            is_snippet_file(&path) && parent.is_some_and(|parent| canonical_folders.contains(&parent))
+        });
+        if !changed {
+            return;
+        }
+        // parse before taking the lock so completion never sees a partial file;
+        // a .code-snippets file can hold snippets of any filetype, so all
+        // folders are reloaded
+        let store = self.snippet_store().await;
+        let (snippets, errors) = tokio::task::spawn_blocking(move || load_snippet_folders(&folders)).await.unwrap_or_default();
+        info!("reloaded {} snippet filetypes", snippets.snippets.len());
+        *store.lock().await = snippets;
+        if !errors.is_empty() {
+            self.client.show_message(MessageType::ERROR, errors.join("\n")).await;
+        }
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        match self.update_settings(&params.settings).await {
+            Ok((previous, current)) => self.apply_settings(&previous, &current).await,
+            Err(e) => self.client.show_message(MessageType::ERROR, e).await,
+        }
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let mut document_lock = self.documents.lock().await;
 
         let uri = params.text_document.uri.to_string();
-        if let Some(content) = document_lock.get_mut(&uri) {
-            self.remove_words(content.clone()).await;
+        if let Some(document) = document_lock.get_mut(&uri) {
+            self.remove_words(document.text().to_string(), &uri).await;
             if let Some(last_change) = params.content_changes.last() {
-                *content = last_change.text.clone();
+                *document = Document::new(last_change.text.clone());
             }
         }
         for content_change in params.content_changes.iter() {
             self.add_words(content_change.text.clone(), params.text_document.uri.to_string()).await;
         }
-        self.maybe_update_tmux().await;
+        drop(document_lock);
+        self.trigger_tmux().await;
+        self.publish_diagnostics(params.text_document.uri.as_str()).await;
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
         let text_document_position = params.text_document_position.clone();
         let position = text_document_position.position;
 
+        let file_uri = params.text_document_position.text_document.uri.to_string();
+        let settings = self.settings().await.for_document(&file_uri);
+        let encoding = *self.position_encoding.lock().await;
         let mut completions = Vec::new();
+        let mut source_ends = Vec::new();
         if let Some(current_line) = self.get_current_line(&params).await {
-            let prefix = get_word_prefix(&current_line, position.character as i32);
-            if prefix.chars().next().map_or(false, |c| c.is_alphabetic()) {
-                let trie_lock = self.trie.lock().await;
-                let words = trie_lock.suggest_completions(&prefix);
-                let suffixes = get_possible_current_word(&current_line, position.character as i32);
-                words_uri_pair_to_completion_items(words, &suffixes, &mut completions, CompletionItemKind::TEXT);
-
-                let tmux_words = self.prepare_tmux_words().await;
-                let source = "tmux".to_string();
-                words_to_completion_items(tmux_words, source, &suffixes, &mut completions, CompletionItemKind::REFERENCE);
-
-                if self.lsp_args.command_source {
-                    let mut command_words = get_command_completions();
-                    command_words.sort();
-                    command_words.dedup();
+            let character = encoding.char_index(&current_line, position.character) as i32;
+            let prefix = get_word_prefix(&current_line, character);
+            if prefix.chars().next().is_some_and(|c| c.is_alphabetic()) {
+                let suffixes = get_possible_current_word(&current_line, character);
+                let word_edit = get_word_edit(&current_line, position, encoding, self.insert_replace_support().await);
+                self.word_items(&settings, &prefix, &suffixes, &word_edit, &mut completions, &mut source_ends).await;
+
+                if settings.command_source {
+                    let command_words = self.commands.lock().await.clone();
                     let source = "command".to_string();
-                    words_to_completion_items(command_words, source, &suffixes, &mut completions, CompletionItemKind::KEYWORD);
+                    words_to_completion_items(command_words, source, &suffixes, &word_edit, &mut completions, CompletionItemKind::KEYWORD);
                 }
+                source_ends.push(("command", completions.len()));
 
-                let file_uri = params.text_document_position.text_document.uri.to_string();
-                let snippets = self.suggest_snippets(&file_uri, &prefix).await;
-                snippets_to_completion_items(snippets, &mut completions);
+                let document_root = self.document_root(&file_uri).await;
+                if let Some(root) = document_root.as_deref().filter(|_| settings.tags_source) {
+                    self.refresh_tags(root).await;
+                    for (name, kind) in self.tags.lock().await.suggest(root, &prefix) {
+                        words_to_completion_items(vec![name], "tags".to_string(), &suffixes, &word_edit, &mut completions, kind);
+                    }
+                }
+                source_ends.push(("tags", completions.len()));
+
+                if settings.snippet_source {
+                    let before: String = current_line.chars().take(character as usize - prefix.chars().count()).collect();
+                    let snippets = self.suggest_snippets(&file_uri, &prefix, &before).await;
+                    snippets_to_completion_items(snippets, &word_edit, self.snippet_support().await, &mut completions);
+                }
+                source_ends.push(("snippet", completions.len()));
 
-                if let Some(root_folder) = self.lsp_args.root_folder.clone() {
-                    let file_items = get_file_items(&current_line, &root_folder);
-                    file_items_to_completion_items(file_items, &params, &mut completions);
+                if let (true, Some(root_folder)) = (settings.file_source, document_root) {
+                    let file_items = get_file_items(&current_line, &root_folder.to_string_lossy());
+                    file_items_to_completion_items(file_items, &current_line, &params, encoding, &mut completions);
                 }
+                source_ends.push(("file", completions.len()));
             }
         }
+        prioritize_sources(&mut completions, &source_ends, &settings);
         Ok(Some(CompletionResponse::Array(completions)))
     }
+
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>> {
+        let uri = params.text_document_position_params.text_document.uri.to_string();
+        let position = params.text_document_position_params.position;
+        let encoding = *self.position_encoding.lock().await;
+        let word = {
+            let document_lock = self.documents.lock().await;
+            let Some(line) = document_lock.get(&uri).and_then(|document| document.line(position.line as usize)) else {
+                return Ok(None);
+            };
+            let Some((start, end)) = get_word_range(line, encoding.char_index(line, position.character)) else {
+                return Ok(None);
+            };
+            line.chars().skip(start).take(end - start).collect::<String>()
+        };
+        if !self.settings().await.for_document(&uri).tags_source {
+            return Ok(None);
+        }
+        let Some(root) = self.document_root(&uri).await else {
+            return Ok(None);
+        };
+        self.refresh_tags(&root).await;
+        let tags = self.tags.lock().await.lookup(&root, &word).to_vec();
+        let mut locations = Vec::new();
+        for tag in tags {
+            let Ok(target) = Url::from_file_path(&tag.file) else {
+                continue;
+            };
+            let open_document = self.documents.lock().await.get(target.as_str()).cloned();
+            let Some(document) = open_document.or_else(|| read_text_file(&tag.file).map(Document::new)) else {
+                continue;
+            };
+            if let Some((line_number, column)) = tag.locate(&document) {
+                let line = document.line(line_number).unwrap_or_default();
+                let start = Position::new(line_number as u32, encoding.column(line, column));
+                let end = Position::new(line_number as u32, encoding.column(line, column + tag.name.len()));
+                locations.push(Location::new(target, Range::new(start, end)));
+            }
+        }
+        if locations.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(GotoDefinitionResponse::Array(locations)))
+    }
+
+    async fn prepare_rename(&self, params: TextDocumentPositionParams) -> Result<Option<PrepareRenameResponse>> {
+        let uri = params.text_document.uri.to_string();
+        let position = params.position;
+        let encoding = *self.position_encoding.lock().await;
+        let document_lock = self.documents.lock().await;
+        let Some(document) = document_lock.get(&uri) else {
+            return Ok(None);
+        };
+        let Some(line) = document.line(position.line as usize) else {
+            return Ok(None);
+        };
+        let Some((start, end)) = get_word_range(line, encoding.char_index(line, position.character)) else {
+            return Ok(None);
+        };
+        let word: String = line.chars().skip(start).take(end - start).collect();
+        if !is_identifier(&word) {
+            return Ok(None);
+        }
+        let range = Range {
+            start: Position::new(position.line, encoding.char_column(line, start)),
+            end: Position::new(position.line, encoding.char_column(line, end)),
+        };
+        let occurrences = find_word_occurrences(document, &word, get_syntax(&uri), encoding);
+        if !occurrences.contains(&range) {
+            return Ok(None);
+        }
+        Ok(Some(PrepareRenameResponse::RangeWithPlaceholder {
+            range,
+            placeholder: word,
+        }))
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let Some(PrepareRenameResponse::RangeWithPlaceholder { placeholder: word, .. }) =
+            self.prepare_rename(params.text_document_position.clone()).await?
+        else {
+            return Ok(None);
+        };
+        if !is_identifier(&params.new_name) {
+            return Err(jsonrpc::Error::invalid_params(format!(
+                "{} is not a valid identifier",
+                params.new_name
+            )));
+        }
+
+        let encoding = *self.position_encoding.lock().await;
+        let open_documents: Vec<(String, Document)> =
+            self.documents.lock().await.iter().map(|(uri, document)| (uri.clone(), document.clone())).collect();
+        let mut changes = std::collections::HashMap::new();
+        for (uri, document) in open_documents.iter() {
+            let edits = rename_edits(document, &word, &params.new_name, get_syntax(uri), encoding);
+            if let (false, Ok(url)) = (edits.is_empty(), Url::parse(uri)) {
+                changes.insert(url, edits);
+            }
+        }
+
+        // reading the workspace files would stall every other request
+        let open_uris: Vec<String> = open_documents.into_iter().map(|(uri, _)| uri).collect();
+        let workspace_files = self.workspace_files.lock().await.clone();
+        let new_name = params.new_name.clone();
+        let workspace_changes = tokio::task::spawn_blocking(move || {
+            let mut changes = Vec::new();
+            for path in workspace_files.iter() {
+                let Ok(url) = Url::from_file_path(path) else {
+                    continue;
+                };
+                if open_uris.iter().any(|uri| uri == url.as_str()) {
+                    continue;
+                }
+                if let Some(content) = read_text_file(path) {
+                    let document = Document::new(content);
+                    let edits = rename_edits(&document, &word, &new_name, get_syntax(url.as_str()), encoding);
+                    if !edits.is_empty() {
+                        changes.push((url, edits));
+                    }
+                }
+            }
+            changes
+        })
+        .await
+        .unwrap_or_default();
+        changes.extend(workspace_changes);
+
+        Ok(Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..WorkspaceEdit::default()
+        }))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let uri = params.text_document.uri.to_string();
+        let capabilities = self.client_capabilities.lock().await.text_document.as_ref().and_then(|t| t.folding_range.clone());
+        let options = FoldingOptions {
+            line_folding_only: capabilities.as_ref().and_then(|c| c.line_folding_only).unwrap_or(false),
+            range_limit: capabilities.as_ref().and_then(|c| c.range_limit).map(|limit| limit as usize),
+            encoding: *self.position_encoding.lock().await,
+        };
+        let document_lock = self.documents.lock().await;
+        let Some(document) = document_lock.get(&uri) else {
+            return Ok(None);
+        };
+        Ok(Some(folding_ranges(document, get_syntax(&uri), options)))
+    }
+
+    async fn document_symbol(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        let hierarchical = self
+            .client_capabilities
+            .lock()
+            .await
+            .text_document
+            .as_ref()
+            .and_then(|t| t.document_symbol.as_ref())
+            .and_then(|d| d.hierarchical_document_symbol_support)
+            .unwrap_or(false);
+        let encoding = *self.position_encoding.lock().await;
+        let document_lock = self.documents.lock().await;
+        let Some(document) = document_lock.get(uri.as_str()) else {
+            return Ok(None);
+        };
+        let user_patterns = self.symbol_patterns.lock().await;
+        Ok(Some(document_symbols(document, &uri, &user_patterns, hierarchical, encoding)))
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let uri = params.text_document.uri;
+        let Some(document) = self.documents.lock().await.get(uri.as_str()).cloned() else {
+            return Ok(None);
+        };
+        let mut bases = Vec::new();
+        if let Some(parent) = uri.to_file_path().ok().as_deref().and_then(Path::parent) {
+            bases.push(parent.to_path_buf());
+        }
+        bases.extend(self.document_root(uri.as_str()).await);
+        let encoding = *self.position_encoding.lock().await;
+        // every path-like token is looked up on disk
+        let links = tokio::task::spawn_blocking(move || find_links(&document, &bases, encoding)).await.unwrap_or_default();
+        Ok(Some(links))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let dictionary = self.dictionary.lock().await;
+        let mut actions = Vec::new();
+        for diagnostic in params.context.diagnostics.iter() {
+            if diagnostic.source.as_deref() != Some(SPELL_SOURCE) {
+                continue;
+            }
+            let Some(Value::String(word)) = diagnostic.data.clone() else {
+                continue;
+            };
+            for suggestion in dictionary.suggest(&word, MAX_SPELL_SUGGESTIONS) {
+                let edit = TextEdit {
+                    range: diagnostic.range,
+                    new_text: suggestion.clone(),
+                };
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: format!("Change to \"{}\"", suggestion),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(std::collections::HashMap::from([(uri.clone(), vec![edit])])),
+                        ..WorkspaceEdit::default()
+                    }),
+                    ..CodeAction::default()
+                }));
+            }
+            let title = format!("Add \"{}\" to project dictionary", word);
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: title.clone(),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                command: Some(Command {
+                    title,
+                    command: ADD_TO_DICTIONARY_COMMAND.to_string(),
+                    arguments: Some(vec![Value::String(word)]),
+                }),
+                ..CodeAction::default()
+            }));
+        }
+        Ok(Some(actions))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        match params.command.as_str() {
+            ADD_TO_DICTIONARY_COMMAND => {
+                let Some(word) = params.arguments.first().and_then(Value::as_str) else {
+                    return Err(jsonrpc::Error::invalid_params("expected a word to add"));
+                };
+                self.add_to_dictionary(word).await?;
+                Ok(None)
+            }
+            RELOAD_SNIPPETS_COMMAND => {
+                self.reload_snippets().await;
+                Ok(Some(self.index_stats().await))
+            }
+            REFRESH_TMUX_COMMAND => {
+                self.maybe_update_tmux().await;
+                Ok(Some(self.index_stats().await))
+            }
+            CLEAR_INDEX_COMMAND => {
+                self.trie.lock().await.clear(self.session_id);
+                self.vocabulary.lock().await.clear();
+                Ok(Some(self.index_stats().await))
+            }
+            REBUILD_INDEX_COMMAND => {
+                self.rebuild_index().await;
+                Ok(Some(self.index_stats().await))
+            }
+            INDEX_STATS_COMMAND => Ok(Some(self.index_stats().await)),
+            TOGGLE_SOURCE_COMMAND => {
+                let source = params.arguments.first().and_then(Value::as_str).unwrap_or_default();
+                if !SOURCES.contains(&source) {
+                    return Err(jsonrpc::Error::invalid_params(format!(
+                        "expected one of {}",
+                        SOURCES.join(", ")
+                    )));
+                }
+                let enabled = match params.arguments.get(1).and_then(Value::as_bool) {
+                    Some(enabled) => enabled,
+                    None => !self.source_enabled(source).await,
+                };
+                let update = parse_settings_update(&json!({ format!("{}_source", source): enabled }))
+                    .map_err(jsonrpc::Error::invalid_params)?;
+                let (previous, current) = self
+                    .update_config(|config| config.overrides = config.overrides.clone().merge(update))
+                    .await
+                    .map_err(jsonrpc::Error::invalid_params)?;
+                self.apply_settings(&previous, &current).await;
+                Ok(Some(json!({ "source": source, "enabled": enabled })))
+            }
+            command => Err(jsonrpc::Error::invalid_params(format!("unknown command {}", command))),
+        }
+    }
+}
+
+// clients of a socket can disconnect without a shutdown request
+impl Drop for Backend {
+    fn drop(&mut self) {
+        if let Some(task) = self.snippet_watcher.get_mut().take() {
+            task.abort();
+        }
+        if !self.daemon {
+            if let Some(task) = self.tmux_task.try_lock().ok().and_then(|mut task| task.take()) {
+                task.abort();
+            }
+        }
+        // the words of a closed session are no longer suggested to the others
+        let (trie, session_id) = (self.trie.clone(), self.session_id);
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { trie.lock().await.clear(session_id) });
+        }
+    }
 }
 
 impl Backend {
-    pub fn new(lsp_args: LspArgs) -> Self {
-        return Self {
+    pub fn new(client: Client, lsp_args: LspArgs, shared: SharedState) -> Self {
+        let mut config = ConfigLayers {
+            cli: lsp_args.settings_update(),
+            ..ConfigLayers::default()
+        };
+        let (settings, errors) = config.resolve_valid();
+        for e in errors {
+            error!("{}", e);
+        }
+        let session_id = shared.next_session_id();
+        Self {
+            client,
             documents: Mutex::new(HashMap::new()),
-            snippets: Mutex::new(HashMap::new()),
-            trie: Mutex::new(Trie::new()),
-            tmux_source: Mutex::new(Vec::new()),
-            lsp_args,
+            snippet_stores: shared.snippets,
+            snippets: Mutex::new(Arc::new(Mutex::new(SnippetStore::default()))),
+            snippet_watcher: Mutex::new(None),
+            trie: shared.trie,
+            session_id,
+            commands: Mutex::new(Vec::new()),
+            tags: Mutex::new(TagIndex::new()),
+            tmux_source: shared.tmux_source,
+            tmux_trigger: shared.tmux_trigger,
+            tmux_settings: shared.tmux_settings,
+            tmux_task: shared.tmux_task,
+            daemon: shared.daemon,
+            workspace_folders: Mutex::new(Vec::new()),
+            workspace_files: Mutex::new(Vec::new()),
+            workspace_lines: Mutex::new(LineIndex::default()),
+            symbol_patterns: Mutex::new(Vec::new()),
+            dictionary: Mutex::new(Dictionary::new()),
+            vocabulary: Mutex::new(Vocabulary::default()),
+            settings: Arc::new(Mutex::new(settings)),
+            config: Mutex::new(config),
+            client_capabilities: Mutex::new(ClientCapabilities::default()),
+            inline_completion_registration: AtomicBool::new(false),
+            position_encoding: Mutex::new(PositionEncoding::default()),
+            progress: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn settings(&self) -> Settings {
+        self.settings.lock().await.clone()
+    }
+
+    pub fn set_inline_completion_registration(&self, dynamic_registration: bool) {
+        self.inline_completion_registration.store(dynamic_registration, Ordering::SeqCst);
+    }
+
+    async fn update_settings(&self, value: &Value) -> std::result::Result<(Settings, Settings), String> {
+        let update = parse_configuration_change(value)?;
+        self.update_config(|config| config.client = config.client.clone().merge(update)).await
+    }
+
+    /// Changes one of the config layers and resolves the settings again. The
+    /// change is dropped when the resulting settings are invalid.
+    async fn update_config(&self, change: impl FnOnce(&mut ConfigLayers)) -> std::result::Result<(Settings, Settings), String> {
+        let current = self.config.lock().await.update(change)?;
+        let mut settings = self.settings.lock().await;
+        let previous = std::mem::replace(&mut *settings, current.clone());
+        info!("settings updated: {:?}", current);
+        Ok((previous, current))
+    }
+
+    /// Reads the user config file and the project config file of the first
+    /// workspace root. Invalid files are reported and ignored.
+    async fn load_config_files(&self) {
+        let mut errors = Vec::new();
+        let mut user = SettingsUpdate::default();
+        if let Some(path) = user_config_path().filter(|path| path.is_file()) {
+            info!("loading user config: {}", path.display());
+            user = load_config_file(&path).unwrap_or_else(|e| {
+                errors.push(e);
+                SettingsUpdate::default()
+            });
+        }
+        self.config.lock().await.user = user;
+        // the project file is looked up from root_folder, which any layer can
+        // set; invalid layers are reported below
+        let (settings, _) = self.config.lock().await.clone().resolve_valid();
+        *self.settings.lock().await = settings;
+        let mut project = SettingsUpdate::default();
+        if let Some(path) = self.workspace_roots().await.first().and_then(|root| find_project_config(root)) {
+            info!("loading project config: {}", path.display());
+            project = load_config_file(&path).unwrap_or_else(|e| {
+                errors.push(e);
+                SettingsUpdate::default()
+            });
+        }
+        let mut config = self.config.lock().await;
+        config.project = project;
+        let (settings, layer_errors) = config.resolve_valid();
+        errors.extend(layer_errors);
+        drop(config);
+        info!("settings: {:?}", settings);
+        *self.settings.lock().await = settings;
+        if !errors.is_empty() {
+            self.client.show_message(MessageType::ERROR, errors.join("\n")).await;
+        }
+    }
+
+    async fn insert_replace_support(&self) -> bool {
+        let capabilities = self.client_capabilities.lock().await;
+        capabilities
+            .text_document
+            .as_ref()
+            .and_then(|t| t.completion.as_ref())
+            .and_then(|c| c.completion_item.as_ref())
+            .and_then(|c| c.insert_replace_support)
+            .unwrap_or(false)
+    }
+
+    async fn snippet_support(&self) -> bool {
+        let capabilities = self.client_capabilities.lock().await;
+        capabilities
+            .text_document
+            .as_ref()
+            .and_then(|t| t.completion.as_ref())
+            .and_then(|c| c.completion_item.as_ref())
+            .and_then(|c| c.snippet_support)
+            .unwrap_or(false)
+    }
+
+    async fn source_enabled(&self, source: &str) -> bool {
+        let settings = self.settings().await;
+        match source {
+            "word" => settings.word_source,
+            "tmux" => settings.tmux_source,
+            "command" => settings.command_source,
+            "snippet" => settings.snippet_source,
+            "file" => settings.file_source,
+            "tags" => settings.tags_source,
+            _ => false,
+        }
+    }
+
+    async fn index_stats(&self) -> Value {
+        let trie_stats = self.trie.lock().await.stats(self.session_id);
+        let snippet_store = self.snippet_store().await;
+        let snippet_lock = snippet_store.lock().await;
+        let mut sources = serde_json::Map::new();
+        for source in SOURCES.iter() {
+            sources.insert(source.to_string(), Value::Bool(self.source_enabled(source).await));
+        }
+        json!({
+            "words": trie_stats.words,
+            "occurrences": trie_stats.occurrences,
+            "nodes": trie_stats.nodes,
+            "documents": self.documents.lock().await.len(),
+            "workspace_files": self.workspace_files.lock().await.len(),
+            "tmux_words": self.tmux_source.lock().await.len(),
+            "tags": self.tags.lock().await.len(),
+            "snippet_filetypes": snippet_lock.snippets.len(),
+            "snippets": snippet_lock.snippet_count(),
+            "sources": sources,
+        })
+    }
+
+    async fn apply_settings(&self, previous: &Settings, current: &Settings) {
+        if previous.all_snippet_folders() != current.all_snippet_folders() {
+            self.reload_snippets().await;
+            self.watch_snippets().await;
+        }
+        if previous.min_word_len != current.min_word_len
+            || previous.index_workspace != current.index_workspace
+            || previous.root_folder != current.root_folder
+        {
+            self.rebuild_index().await;
+        }
+        if previous.command_source != current.command_source {
+            self.refresh_commands().await;
+        }
+        if previous.tmux_source != current.tmux_source || previous.min_word_len != current.min_word_len {
+            // scrape right away instead of waiting for the next edit
+            self.maybe_update_tmux().await;
+        }
+        if previous.spell_check_enabled() != current.spell_check_enabled()
+            || previous.dictionary != current.dictionary
+            || previous.root_folder != current.root_folder
+        {
+            self.load_dictionaries().await;
+            self.refresh_diagnostics().await;
+        } else if previous.marker_check != current.marker_check
+            || previous.markers != current.markers
+            || previous.marker_severity != current.marker_severity
+            || previous.languages != current.languages
+        {
+            self.refresh_diagnostics().await;
+        }
+        if previous.symbol_patterns != current.symbol_patterns {
+            let errors = self.compile_symbol_patterns().await;
+            if !errors.is_empty() {
+                self.client.show_message(MessageType::ERROR, errors.join("\n")).await;
+            }
+        }
+    }
+
+    /// Compiles the user symbol patterns of the current settings. Invalid
+    /// patterns are left out and their errors returned.
+    async fn compile_symbol_patterns(&self) -> Vec<String> {
+        let mut patterns = Vec::new();
+        let mut errors = Vec::new();
+        for config in self.settings().await.symbol_patterns.iter() {
+            match SymbolPattern::from_config(config) {
+                Ok(pattern) => patterns.push(pattern),
+                Err(e) => errors.push(e),
+            }
+        }
+        *self.symbol_patterns.lock().await = patterns;
+        errors
+    }
+
+    async fn begin_progress(&self, title: &str) -> ProgressReporter {
+        let supported = self
+            .client_capabilities
+            .lock()
+            .await
+            .window
+            .as_ref()
+            .and_then(|w| w.work_done_progress)
+            .unwrap_or(false);
+        let progress = ProgressReporter::begin(&self.client, title, supported).await;
+        self.progress.lock().await.insert(token_key(progress.token()), progress.cancel_flag());
+        progress
+    }
+
+    async fn end_progress(&self, progress: ProgressReporter, message: String) {
+        self.progress.lock().await.remove(&token_key(progress.token()));
+        progress.end(message).await;
+    }
+
+    pub async fn cancel_progress(&self, params: WorkDoneProgressCancelParams) {
+        if let Some(cancelled) = self.progress.lock().await.get(&token_key(&params.token)) {
+            info!("cancel progress {}", token_key(&params.token));
+            cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    async fn reload_snippets(&self) {
+        let store = self.snippet_store().await;
+        let mut snippets = SnippetStore::default();
+        let mut errors = Vec::new();
+        let folders = self.settings().await.all_snippet_folders();
+        if !folders.is_empty() {
+            info!("loading snippet folders: {:?}", folders);
+            let mut progress = self.begin_progress("Loading snippets").await;
+            let files: Vec<PathBuf> = folders.iter().flat_map(|folder| list_snippet_files(folder)).collect();
+            for (i, path) in files.iter().enumerate() {
+                if progress.is_cancelled() {
+                    break;
+                }
+                progress.report(i, files.len(), path.display().to_string()).await;
+                let mut file = load_snippet_file(path);
+                errors.append(&mut file.errors);
+                snippets.add(file);
+            }
+            self.end_progress(progress, format!("{} snippets", snippets.snippet_count())).await;
+        }
+        *store.lock().await = snippets;
+        if !errors.is_empty() {
+            self.client.show_message(MessageType::ERROR, errors.join("\n")).await;
+        }
+    }
+
+    /// Watches the snippet folder through the client when it supports dynamic
+    /// registration of file watchers, or by polling otherwise.
+    async fn watch_snippets(&self) {
+        let dynamic_registration = self
+            .client_capabilities
+            .lock()
+            .await
+            .workspace
+            .as_ref()
+            .and_then(|w| w.did_change_watched_files)
+            .and_then(|d| d.dynamic_registration)
+            .unwrap_or(false);
+        if !dynamic_registration {
+            let mut watcher = self.snippet_watcher.lock().await;
+            if self.settings().await.all_snippet_folders().is_empty() {
+                if let Some(task) = watcher.take() {
+                    task.abort();
+                }
+            } else if watcher.is_none() {
+                let task = tokio::spawn(poll_snippet_folder(self.client.clone(), self.settings.clone(), self.snippet_stores.clone()));
+                *watcher = Some(task);
+            }
+            return;
+        }
+
+        let _ = self
+            .client
+            .unregister_capability(vec![Unregistration {
+                id: SNIPPET_WATCHER_ID.to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+            }])
+            .await;
+        let folders = self.settings().await.all_snippet_folders();
+        if folders.is_empty() {
+            return;
+        }
+        let watchers = folders
+            .iter()
+            .map(|folder| {
+                let folder = Path::new(folder).canonicalize().unwrap_or(PathBuf::from(folder));
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(format!("{}/*.{{{}}}", folder.display(), SNIPPET_EXTENSIONS.join(","))),
+                    kind: None,
+                }
+            })
+            .collect();
+        let options = DidChangeWatchedFilesRegistrationOptions { watchers };
+        let registration = Registration {
+            id: SNIPPET_WATCHER_ID.to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(options).ok(),
         };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            warn!("fail to watch snippet folder: {}", e);
+        }
+    }
+
+    async fn rebuild_index(&self) {
+        let document_lock = self.documents.lock().await;
+        self.trie.lock().await.clear(self.session_id);
+        self.vocabulary.lock().await.clear();
+        for (uri, document) in document_lock.iter() {
+            self.add_words(document.text().to_string(), uri.clone()).await;
+        }
+        drop(document_lock);
+        self.index_workspace().await;
     }
 
     async fn add_words(&self, content: String, uri: String) {
+        self.vocabulary.lock().await.add(&uri, &content);
+        let min_word_len = self.settings().await.min_word_len;
         let mut trie_lock = self.trie.lock().await;
+        let trie = trie_lock.trie(self.session_id);
         for token in content.split_whitespace() {
-            let words = process_token(token, self.lsp_args.min_word_len);
+            let words = process_token(token, min_word_len);
             for w in words {
-                trie_lock.insert(&w, &uri);
+                trie.insert(&w, &uri);
             }
         }
     }
 
-    async fn remove_words(&self, content: String) {
+    async fn remove_words(&self, content: String, uri: &str) {
+        self.vocabulary.lock().await.remove(uri, &content);
+        let min_word_len = self.settings().await.min_word_len;
         let mut trie_lock = self.trie.lock().await;
+        let trie = trie_lock.trie(self.session_id);
         for token in content.split_whitespace() {
-            let words = process_token(token, self.lsp_args.min_word_len);
+            let words = process_token(token, min_word_len);
             for w in words {
-                trie_lock.remove(&w);
+                trie.remove(&w);
+            }
+        }
+    }
+
+    async fn index_workspace(&self) {
+        let roots = self.workspace_roots().await;
+        self.workspace_lines.lock().await.clear();
+        if !self.settings().await.index_workspace || roots.is_empty() {
+            self.workspace_files.lock().await.clear();
+            return;
+        }
+
+        info!("indexing workspace: {:?}", roots);
+        let mut progress = self.begin_progress("Indexing workspace").await;
+        let files = tokio::task::spawn_blocking(move || {
+            let mut files: Vec<PathBuf> = Vec::new();
+            for root in roots {
+                let root = root.canonicalize().unwrap_or(root);
+                for path in list_workspace_files(&root) {
+                    if !files.contains(&path) {
+                        files.push(path);
+                    }
+                }
+            }
+            files
+        })
+        .await
+        .unwrap_or_default();
+
+        let mut indexed = Vec::new();
+        for (i, chunk) in files.chunks(INDEX_CHUNK_SIZE).enumerate() {
+            if progress.is_cancelled() {
+                break;
+            }
+            progress.report(i * INDEX_CHUNK_SIZE, files.len(), chunk[0].display().to_string()).await;
+            let chunk = chunk.to_vec();
+            let contents = tokio::task::spawn_blocking(move || {
+                chunk
+                    .into_iter()
+                    .filter_map(|path| read_text_file(&path).map(|content| (path, content)))
+                    .collect::<Vec<(PathBuf, String)>>()
+            })
+            .await
+            .unwrap_or_default();
+            for (path, content) in contents {
+                if let Ok(url) = Url::from_file_path(&path) {
+                    let document = Document::new(content);
+                    let lines = (0..document.line_count()).filter_map(|n| document.line(n));
+                    self.workspace_lines.lock().await.add(lines);
+                    self.add_words(document.text().to_string(), url.to_string()).await;
+                    indexed.push(path);
+                }
+            }
+        }
+        info!("indexed {} workspace files", indexed.len());
+        let message = format!("{} files", indexed.len());
+        *self.workspace_files.lock().await = indexed;
+        self.end_progress(progress, message).await;
+    }
+
+    async fn refresh_commands(&self) {
+        if !self.settings().await.command_source {
+            self.commands.lock().await.clear();
+            return;
+        }
+        let mut progress = self.begin_progress("Scanning PATH").await;
+        let directories = get_path_directories();
+        let mut commands = Vec::new();
+        for (i, directory) in directories.iter().enumerate() {
+            if progress.is_cancelled() {
+                break;
+            }
+            progress.report(i, directories.len(), directory.display().to_string()).await;
+            let directory = directory.clone();
+            commands.extend(tokio::task::spawn_blocking(move || get_directory_commands(&directory)).await.unwrap_or_default());
+        }
+        commands.sort();
+        commands.dedup();
+        let message = format!("{} commands", commands.len());
+        *self.commands.lock().await = commands;
+        self.end_progress(progress, message).await;
+    }
+
+    /// Folders searched by workspace wide features: the client's workspace
+    /// folders, or `root_folder` when the client sent none.
+    async fn workspace_roots(&self) -> Vec<PathBuf> {
+        let workspace_folders = self.workspace_folders.lock().await.clone();
+        if !workspace_folders.is_empty() {
+            return workspace_folders;
+        }
+        self.settings().await.root_folder.map(PathBuf::from).into_iter().collect()
+    }
+
+    /// The folder relative paths in `uri` resolve against: the innermost
+    /// workspace folder or `root_folder` containing it, then its git root.
+    async fn document_root(&self, uri: &str) -> Option<PathBuf> {
+        let root_folder = self.settings().await.root_folder.map(PathBuf::from);
+        let mut folders = self.workspace_folders.lock().await.clone();
+        let Some(path) = Url::parse(uri).ok().and_then(|url| url.to_file_path().ok()) else {
+            return root_folder.or(folders.into_iter().next());
+        };
+        folders.extend(root_folder.iter().filter_map(|root| root.canonicalize().ok()));
+        containing_folder(&folders, &path)
+            .cloned()
+            .or_else(|| find_git_root(&path))
+            .or(root_folder)
+    }
+
+    /// Reloads the tags file under `root` when it appeared, disappeared or
+    /// changed on disk since it was last loaded. The disk is looked at only
+    /// every few seconds.
+    async fn refresh_tags(&self, root: &Path) {
+        let mut tags_lock = self.tags.lock().await;
+        if !tags_lock.is_due(root, Instant::now()) {
+            return;
+        }
+        let path = find_tags_file(root);
+        let modified = path.as_ref().and_then(|p| p.metadata().ok()).and_then(|m| m.modified().ok());
+        if !tags_lock.is_stale(root, path.as_ref(), modified) {
+            return;
+        }
+        let tags = match path.clone() {
+            Some(path) => tokio::task::spawn_blocking(move || load_tags(&path)).await.unwrap_or_default(),
+            None => Vec::new(),
+        };
+        info!("loaded {} tags from {:?}", tags.len(), path);
+        tags_lock.replace(root, path, modified, tags);
+    }
+
+    async fn project_dictionary_path(&self) -> Option<PathBuf> {
+        let root = self.workspace_roots().await.into_iter().next()?;
+        Some(root.join(PROJECT_DICTIONARY))
+    }
+
+    async fn load_dictionaries(&self) {
+        let settings = self.settings().await;
+        let mut dictionary = Dictionary::new();
+        if settings.spell_check_enabled() {
+            let path = settings.dictionary.unwrap_or(DEFAULT_DICTIONARY.to_string());
+            if !dictionary.load_base(Path::new(&path)) {
+                warn!("fail to load dictionary {}", path);
+            }
+            if let Some(project_dictionary) = self.project_dictionary_path().await {
+                dictionary.load(&project_dictionary);
             }
         }
+        *self.dictionary.lock().await = dictionary;
+    }
+
+    async fn add_to_dictionary(&self, word: &str) -> Result<()> {
+        self.dictionary.lock().await.insert(word);
+        match self.project_dictionary_path().await {
+            Some(project_dictionary) => {
+                if let Err(e) = append_to_dictionary(&project_dictionary, word) {
+                    error!("fail to update {}: {}", project_dictionary.display(), e);
+                    return Err(jsonrpc::Error::internal_error());
+                }
+            }
+            None => {
+                let message = format!("no workspace root, \"{}\" is not saved and only known in this session", word);
+                self.client.show_message(MessageType::WARNING, message).await;
+            }
+        }
+
+        self.refresh_diagnostics().await;
+        Ok(())
+    }
+
+    async fn spelling_diagnostics(&self, uri: &str, document: &Document) -> Vec<Diagnostic> {
+        let encoding = *self.position_encoding.lock().await;
+        let dictionary = self.dictionary.lock().await;
+        if !dictionary.is_loaded() {
+            return Vec::new();
+        }
+        // a typo repeated in the document itself does not make it a word
+        let vocabulary = self.vocabulary.lock().await;
+        let is_known = |word: &str| dictionary.contains(word) || vocabulary.count_elsewhere(word, uri) >= MIN_VOCABULARY_COUNT;
+        find_misspellings(document.text(), uri, is_known)
+            .into_iter()
+            .map(|(start, end, word)| Diagnostic {
+                range: Range {
+                    start: document.offset_to_position(start, encoding),
+                    end: document.offset_to_position(end, encoding),
+                },
+                severity: Some(DiagnosticSeverity::INFORMATION),
+                source: Some(SPELL_SOURCE.to_string()),
+                message: format!("Unknown word \"{}\"", word),
+                data: Some(Value::String(word)),
+                ..Diagnostic::default()
+            })
+            .collect()
+    }
+
+    fn marker_diagnostics(&self, uri: &str, document: &Document, settings: &Settings, encoding: PositionEncoding) -> Vec<Diagnostic> {
+        let severity = parse_severity(&settings.marker_severity).unwrap_or(DiagnosticSeverity::HINT);
+        find_markers(document.text(), &settings.markers, get_syntax(uri))
+            .into_iter()
+            .map(|marker| Diagnostic {
+                range: Range {
+                    start: document.offset_to_position(marker.start, encoding),
+                    end: document.offset_to_position(marker.end, encoding),
+                },
+                severity: Some(severity),
+                source: Some(MARKER_SOURCE.to_string()),
+                message: marker.text,
+                ..Diagnostic::default()
+            })
+            .collect()
+    }
+
+    async fn publish_diagnostics(&self, uri: &str) {
+        let settings = self.settings().await;
+        if !settings.diagnostics_enabled() {
+            return;
+        }
+        let settings = settings.for_document(uri);
+        let Ok(url) = Url::parse(uri) else {
+            return;
+        };
+        let Some(document) = self.documents.lock().await.get(uri).cloned() else {
+            return;
+        };
+        let mut diagnostics = Vec::new();
+        if settings.spell_check {
+            diagnostics.extend(self.spelling_diagnostics(uri, &document).await);
+        }
+        if settings.marker_check {
+            let encoding = *self.position_encoding.lock().await;
+            diagnostics.extend(self.marker_diagnostics(uri, &document, &settings, encoding));
+        }
+        self.client.publish_diagnostics(url, diagnostics, None).await;
+    }
+
+    async fn refresh_diagnostics(&self) {
+        let enabled = self.settings().await.diagnostics_enabled();
+        let uris: Vec<String> = self.documents.lock().await.keys().cloned().collect();
+        for uri in uris.iter() {
+            if enabled {
+                self.publish_diagnostics(uri).await;
+            } else if let Ok(url) = Url::parse(uri) {
+                self.client.publish_diagnostics(url, Vec::new(), None).await;
+            }
+        }
+    }
+
+    pub async fn list_markers(&self, params: ListMarkersParams) -> Result<Vec<MarkerLocation>> {
+        let markers = params.markers.unwrap_or(self.settings().await.markers);
+        let encoding = *self.position_encoding.lock().await;
+        let open_documents: Vec<(String, Document)> =
+            self.documents.lock().await.iter().map(|(uri, document)| (uri.clone(), document.clone())).collect();
+        let open_uris: Vec<String> = open_documents.iter().map(|(uri, _)| uri.clone()).collect();
+        let workspace_files = self.workspace_files.lock().await.clone();
+        let workspace_documents = tokio::task::spawn_blocking(move || {
+            workspace_files
+                .into_iter()
+                .filter_map(|path| Url::from_file_path(&path).ok().map(|url| (path, url.to_string())))
+                .filter(|(_, uri)| !open_uris.contains(uri))
+                .filter_map(|(path, uri)| read_text_file(&path).map(|text| (uri, Document::new(text))))
+                .collect::<Vec<(String, Document)>>()
+        })
+        .await
+        .unwrap_or_default();
+
+        let mut locations = Vec::new();
+        for (uri, document) in open_documents.into_iter().chain(workspace_documents) {
+            let Ok(url) = Url::parse(&uri) else {
+                continue;
+            };
+            for marker in find_markers(document.text(), &markers, get_syntax(&uri)) {
+                locations.push(MarkerLocation {
+                    uri: url.clone(),
+                    range: Range {
+                        start: document.offset_to_position(marker.start, encoding),
+                        end: document.offset_to_position(marker.end, encoding),
+                    },
+                    marker: marker.word,
+                    text: marker.text,
+                });
+            }
+        }
+        locations.sort_by(|a, b| (a.uri.as_str(), a.range.start).cmp(&(b.uri.as_str(), b.range.start)));
+        Ok(locations)
+    }
+
+    // lsp-types has no static capability for inline completion yet, so it is
+    // registered dynamically, when the client can register it.
+    async fn register_inline_completion(&self) {
+        if !self.inline_completion_registration.load(Ordering::SeqCst) {
+            info!("inline completion not registered: no dynamic registration support");
+            return;
+        }
+        let registration = Registration {
+            id: INLINE_COMPLETION_METHOD.to_string(),
+            method: INLINE_COMPLETION_METHOD.to_string(),
+            register_options: None,
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            info!("inline completion not registered: {}", e);
+        }
+    }
+
+    pub async fn inline_completion(&self, params: InlineCompletionParams) -> Result<Option<InlineCompletionList>> {
+        let uri = params.text_document.uri.to_string();
+        let position = params.position;
+        let settings = self.settings().await.for_document(&uri);
+        let encoding = *self.position_encoding.lock().await;
+        let mut items = Vec::new();
+        let line = {
+            let document_lock = self.documents.lock().await;
+            let Some(line) = document_lock.get(&uri).and_then(|document| document.line(position.line as usize)) else {
+                return Ok(None);
+            };
+            let cursor = encoding.byte_offset(line, position.character);
+            if !line[cursor..].trim().is_empty() {
+                return Ok(None);
+            }
+            let prefix = line[..cursor].trim_start();
+            let indent = cursor - prefix.len();
+            let lines = document_lock.iter().flat_map(|(document_uri, document)| {
+                let current = document_uri == &uri;
+                (0..document.line_count())
+                    .filter(move |&n| !current || n != position.line as usize)
+                    .filter_map(|n| document.line(n))
+            });
+            // workspace files count with their lines as they were when indexed
+            let workspace_lines = self.workspace_lines.lock().await;
+            for text in line_completions(prefix, lines, &workspace_lines) {
+                items.push(InlineCompletionItem {
+                    insert_text: text,
+                    filter_text: None,
+                    range: Some(Range {
+                        start: Position::new(position.line, encoding.column(line, indent)),
+                        end: position,
+                    }),
+                });
+            }
+            line.to_string()
+        };
+
+        let character = encoding.char_index(&line, position.character);
+        let prefix = get_word_prefix(&line, character as i32);
+        if prefix.chars().next().is_some_and(|c| c.is_alphabetic()) {
+            // the words are ranked as regular completion ranks them
+            let suffixes = get_possible_current_word(&line, character as i32);
+            let word_edit = get_word_edit(&line, position, encoding, false);
+            let mut completions = Vec::new();
+            let mut source_ends = Vec::new();
+            self.word_items(&settings, &prefix, &suffixes, &word_edit, &mut completions, &mut source_ends).await;
+            prioritize_sources(&mut completions, &source_ends, &settings);
+            let start = Position::new(position.line, encoding.char_column(&line, character - prefix.chars().count()));
+            for word in word_completions(&prefix, completions) {
+                items.push(InlineCompletionItem {
+                    insert_text: word,
+                    filter_text: None,
+                    range: Some(Range { start, end: position }),
+                });
+            }
+        }
+        Ok(Some(InlineCompletionList { items }))
+    }
+
+    /// Adds the items of the word and tmux sources, which inline completion
+    /// offers as well, and where each source's items end.
+    async fn word_items(
+        &self,
+        settings: &Settings,
+        prefix: &str,
+        suffixes: &[String],
+        word_edit: &WordEdit,
+        completions: &mut Vec<CompletionItem>,
+        source_ends: &mut Vec<(&'static str, usize)>,
+    ) {
+        if settings.word_source {
+            let trie_lock = self.trie.lock().await;
+            let words = trie_lock.suggest_completions(prefix);
+            words_uri_pair_to_completion_items(words, suffixes, word_edit, completions, CompletionItemKind::TEXT);
+        }
+        source_ends.push(("word", completions.len()));
+
+        if settings.tmux_source {
+            let tmux_words = self.prepare_tmux_words().await;
+            let source = "tmux".to_string();
+            words_to_completion_items(tmux_words, source, suffixes, word_edit, completions, CompletionItemKind::REFERENCE);
+        }
+        source_ends.push(("tmux", completions.len()));
     }
 
     async fn get_current_line(&self, params: &CompletionParams) -> Option<String> {
@@ -183,8 +1461,8 @@ impl Backend {
         let uri = text_document_position.text_document.uri.to_string();
         let document_lock = self.documents.lock().await;
         let position = text_document_position.position;
-        if let Some(content) = document_lock.get(&uri) {
-            let current_line: Option<&str> = content.split("\n").nth(position.line as usize);
+        if let Some(document) = document_lock.get(&uri) {
+            let current_line: Option<&str> = document.line(position.line as usize);
             if let Some(line) = current_line {
                 return Some(line.to_string());
             }
@@ -192,28 +1470,34 @@ impl Backend {
         None
     }
 
-    async fn suggest_snippets(&self, file_uri: &str, prefix: &str) -> Vec<Snippet> {
-        let snippet_lock = self.snippets.lock().await;
-        let snippet_names = get_snippet_names(file_uri);
-        let mut result = Vec::new();
-        for &snippet_name in snippet_names.iter() {
-            if let Some(snippets) = snippet_lock.get(snippet_name) {
-                for snippet in snippets.iter() {
-                    if snippet.name.contains(prefix) {
-                        result.push(snippet.clone());
-                    }
-                }
-            }
-        }
-        result
+    async fn suggest_snippets(&self, file_uri: &str, prefix: &str, before: &str) -> Vec<Snippet> {
+        self.snippet_store().await.lock().await.suggest(&get_snippet_names(file_uri), prefix, before)
+    }
+
+    /// The snippet store of the current snippet folders, shared with the
+    /// daemon sessions that use the same folders.
+    async fn snippet_store(&self) -> SharedSnippetStore {
+        let folders = self.settings().await.all_snippet_folders();
+        let store = self.snippet_stores.get(&folders).await;
+        *self.snippets.lock().await = store.clone();
+        store
+    }
+
+    /// Wakes the tmux task with the settings of this session.
+    async fn trigger_tmux(&self) {
+        *self.tmux_settings.lock().await = self.settings().await;
+        self.tmux_trigger.notify_one();
     }
 
     async fn maybe_update_tmux(&self) {
-        if self.lsp_args.tmux_source {
-            let tmux_content = retrieve_tmux_words(self.lsp_args.min_word_len);
+        let settings = self.settings().await;
+        if settings.tmux_source {
+            let tmux_content = scrape_tmux_words(settings.min_word_len).await;
             let mut data = self.tmux_source.lock().await;
             data.clear();
             data.extend(tmux_content);
+        } else {
+            self.tmux_source.lock().await.clear();
         }
     }
 
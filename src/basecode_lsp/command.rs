@@ -2,6 +2,7 @@ use std::env;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use std::path::PathBuf;
 
 fn is_executable(path: &Path) -> bool {
     if let Ok(metadata) = fs::metadata(path) {
@@ -11,19 +12,22 @@ fn is_executable(path: &Path) -> bool {
     false
 }
 
-pub fn get_command_completions() -> Vec<String> {
+pub fn get_path_directories() -> Vec<PathBuf> {
+    match env::var("PATH") {
+        Ok(path_var) => path_var.split(':').filter(|p| !p.is_empty()).map(PathBuf::from).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn get_directory_commands(directory: &Path) -> Vec<String> {
     let mut commands = Vec::new();
-    if let Ok(path_var) = env::var("PATH") {
-        for path in path_var.split(':') {
-            if let Ok(entries) = fs::read_dir(path) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
-                    if is_executable(&path) {
-                        if let Some(command) = path.file_name() {
-                            if let Some(command_str) = command.to_str() {
-                                commands.push(command_str.to_string());
-                            }
-                        }
+    if let Ok(entries) = fs::read_dir(directory) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if is_executable(&path) {
+                if let Some(command) = path.file_name() {
+                    if let Some(command_str) = command.to_str() {
+                        commands.push(command_str.to_string());
                     }
                 }
             }
@@ -38,7 +42,10 @@ mod tests {
 
     #[test]
     fn test_get_command_completion() {
-        let items = get_command_completions();
+        let items: Vec<String> = get_path_directories()
+            .iter()
+            .flat_map(|directory| get_directory_commands(directory))
+            .collect();
         // for item in items.iter() {
         //     println!("item = {}", item);
         // }
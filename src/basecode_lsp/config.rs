@@ -0,0 +1,631 @@
+use super::marker::*;
+use super::snippet::get_snippet_names;
+use super::symbol::SymbolPattern;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+pub const SETTINGS_SECTION: &str = "basecode";
+pub const SOURCES: [&str; 6] = ["word", "tmux", "command", "snippet", "file", "tags"];
+pub const USER_CONFIG_FILE: &str = "basecode-lsp/config.toml";
+pub const PROJECT_CONFIG_FILE: &str = ".basecode-lsp.toml";
+const PRIORITY_OFFSET: i32 = 50000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub snippet_folder: Option<String>,
+    pub root_folder: Option<String>,
+    pub min_word_len: usize,
+    pub word_source: bool,
+    pub tmux_source: bool,
+    pub tmux_interval: u64,
+    pub tmux_debounce: u64,
+    pub command_source: bool,
+    pub snippet_source: bool,
+    pub file_source: bool,
+    pub tags_source: bool,
+    pub index_workspace: bool,
+    pub spell_check: bool,
+    pub dictionary: Option<String>,
+    pub marker_check: bool,
+    pub markers: Vec<String>,
+    pub marker_severity: String,
+    pub symbol_patterns: Vec<SymbolPatternConfig>,
+    pub snippet_folders: Vec<String>,
+    pub source_priorities: BTreeMap<String, i32>,
+    pub languages: BTreeMap<String, LanguageSettings>,
+}
+
+/// Overrides for the documents of one language. Without `files` the language
+/// name is matched against the snippet filetypes, e.g. `rust` or `python`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanguageSettings {
+    pub files: Option<Vec<String>>,
+    pub word_source: Option<bool>,
+    pub tmux_source: Option<bool>,
+    pub command_source: Option<bool>,
+    pub snippet_source: Option<bool>,
+    pub file_source: Option<bool>,
+    pub tags_source: Option<bool>,
+    pub spell_check: Option<bool>,
+    pub marker_check: Option<bool>,
+    pub source_priorities: BTreeMap<String, i32>,
+}
+
+impl LanguageSettings {
+    fn matches(&self, name: &str, file_uri: &str) -> bool {
+        match &self.files {
+            Some(files) => files.iter().any(|f| file_uri.ends_with(f.as_str())),
+            None => get_snippet_names(file_uri).contains(&name),
+        }
+    }
+
+    fn apply_to(&self, settings: &mut Settings) {
+        settings.word_source = self.word_source.unwrap_or(settings.word_source);
+        settings.tmux_source = self.tmux_source.unwrap_or(settings.tmux_source);
+        settings.command_source = self.command_source.unwrap_or(settings.command_source);
+        settings.snippet_source = self.snippet_source.unwrap_or(settings.snippet_source);
+        settings.file_source = self.file_source.unwrap_or(settings.file_source);
+        settings.tags_source = self.tags_source.unwrap_or(settings.tags_source);
+        settings.spell_check = self.spell_check.unwrap_or(settings.spell_check);
+        settings.marker_check = self.marker_check.unwrap_or(settings.marker_check);
+        settings.source_priorities.extend(self.source_priorities.clone());
+    }
+}
+
+/// A user supplied document symbol pattern. `pattern` must contain a
+/// `(?P<name>...)` group and may contain a `(?P<level>...)` group whose length
+/// sets the nesting depth.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SymbolPatternConfig {
+    pub files: Vec<String>,
+    pub pattern: String,
+    pub kind: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            snippet_folder: None,
+            root_folder: None,
+            min_word_len: 2,
+            word_source: true,
+            tmux_source: true,
+            tmux_interval: 5,
+            tmux_debounce: 500,
+            command_source: false,
+            snippet_source: true,
+            file_source: true,
+            tags_source: true,
+            index_workspace: false,
+            spell_check: false,
+            dictionary: None,
+            marker_check: false,
+            markers: DEFAULT_MARKERS.iter().map(|m| m.to_string()).collect(),
+            marker_severity: "hint".to_string(),
+            symbol_patterns: Vec::new(),
+            snippet_folders: Vec::new(),
+            source_priorities: BTreeMap::new(),
+            languages: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettingsUpdate {
+    pub snippet_folder: Option<String>,
+    pub root_folder: Option<String>,
+    pub min_word_len: Option<usize>,
+    pub word_source: Option<bool>,
+    pub tmux_source: Option<bool>,
+    pub tmux_interval: Option<u64>,
+    pub tmux_debounce: Option<u64>,
+    pub command_source: Option<bool>,
+    pub snippet_source: Option<bool>,
+    pub file_source: Option<bool>,
+    pub tags_source: Option<bool>,
+    pub index_workspace: Option<bool>,
+    pub spell_check: Option<bool>,
+    pub dictionary: Option<String>,
+    pub marker_check: Option<bool>,
+    pub markers: Option<Vec<String>>,
+    pub marker_severity: Option<String>,
+    pub symbol_patterns: Option<Vec<SymbolPatternConfig>>,
+    pub snippet_folders: Option<Vec<String>>,
+    pub source_priorities: Option<BTreeMap<String, i32>>,
+    pub languages: Option<BTreeMap<String, LanguageSettings>>,
+}
+
+fn merge_maps<V>(base: Option<BTreeMap<String, V>>, other: Option<BTreeMap<String, V>>) -> Option<BTreeMap<String, V>> {
+    match (base, other) {
+        (Some(mut base), Some(other)) => {
+            base.extend(other);
+            Some(base)
+        }
+        (base, other) => other.or(base),
+    }
+}
+
+fn resolve_path(path: &str, base: &Path) -> String {
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = home::home_dir() {
+            return home.join(rest).display().to_string();
+        }
+    }
+    base.join(path).display().to_string()
+}
+
+impl SettingsUpdate {
+    /// Combines two updates, `other` winning where both set a value. Maps are
+    /// merged key by key.
+    pub fn merge(self, other: SettingsUpdate) -> SettingsUpdate {
+        SettingsUpdate {
+            snippet_folder: other.snippet_folder.or(self.snippet_folder),
+            root_folder: other.root_folder.or(self.root_folder),
+            min_word_len: other.min_word_len.or(self.min_word_len),
+            word_source: other.word_source.or(self.word_source),
+            tmux_source: other.tmux_source.or(self.tmux_source),
+            tmux_interval: other.tmux_interval.or(self.tmux_interval),
+            tmux_debounce: other.tmux_debounce.or(self.tmux_debounce),
+            command_source: other.command_source.or(self.command_source),
+            snippet_source: other.snippet_source.or(self.snippet_source),
+            file_source: other.file_source.or(self.file_source),
+            tags_source: other.tags_source.or(self.tags_source),
+            index_workspace: other.index_workspace.or(self.index_workspace),
+            spell_check: other.spell_check.or(self.spell_check),
+            dictionary: other.dictionary.or(self.dictionary),
+            marker_check: other.marker_check.or(self.marker_check),
+            markers: other.markers.or(self.markers),
+            marker_severity: other.marker_severity.or(self.marker_severity),
+            symbol_patterns: other.symbol_patterns.or(self.symbol_patterns),
+            snippet_folders: other.snippet_folders.or(self.snippet_folders),
+            source_priorities: merge_maps(self.source_priorities, other.source_priorities),
+            languages: merge_maps(self.languages, other.languages),
+        }
+    }
+
+    /// Resolves relative paths and `~` against `base`, the folder of the
+    /// config file they were read from.
+    fn resolve_paths(&mut self, base: &Path) {
+        for path in [&mut self.snippet_folder, &mut self.root_folder, &mut self.dictionary].into_iter().flatten() {
+            *path = resolve_path(path, base);
+        }
+        for path in self.snippet_folders.iter_mut().flatten() {
+            *path = resolve_path(path, base);
+        }
+    }
+}
+
+/// The layers settings are resolved from, in increasing order of precedence.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLayers {
+    pub user: SettingsUpdate,
+    pub project: SettingsUpdate,
+    pub client: SettingsUpdate,
+    pub cli: SettingsUpdate,
+    /// Runtime changes such as `basecode.toggleSource`.
+    pub overrides: SettingsUpdate,
+}
+
+impl ConfigLayers {
+    pub fn merged(&self) -> SettingsUpdate {
+        self.user
+            .clone()
+            .merge(self.project.clone())
+            .merge(self.client.clone())
+            .merge(self.cli.clone())
+            .merge(self.overrides.clone())
+    }
+
+    pub fn resolve(&self) -> Result<Settings, String> {
+        Settings::default().apply(self.merged())
+    }
+
+    /// Resolves the settings, leaving out and clearing each layer that makes
+    /// them invalid. Returns the errors of the layers left out.
+    pub fn resolve_valid(&mut self) -> (Settings, Vec<String>) {
+        let mut settings = Settings::default();
+        let mut merged = SettingsUpdate::default();
+        let mut errors = Vec::new();
+        let layers = [
+            ("user", &mut self.user),
+            ("project", &mut self.project),
+            ("client", &mut self.client),
+            ("command line", &mut self.cli),
+            ("runtime", &mut self.overrides),
+        ];
+        for (name, layer) in layers {
+            let candidate = merged.clone().merge(layer.clone());
+            match Settings::default().apply(candidate.clone()) {
+                Ok(valid) => {
+                    settings = valid;
+                    merged = candidate;
+                }
+                Err(e) => {
+                    errors.push(format!("{} settings ignored: {}", name, e));
+                    *layer = SettingsUpdate::default();
+                }
+            }
+        }
+        (settings, errors)
+    }
+
+    /// Applies `change` when the settings it resolves to are valid. Otherwise
+    /// the layers stay as they were.
+    pub fn update(&mut self, change: impl FnOnce(&mut ConfigLayers)) -> Result<Settings, String> {
+        let mut config = self.clone();
+        change(&mut config);
+        let settings = config.resolve()?;
+        *self = config;
+        Ok(settings)
+    }
+}
+
+/// Checks that `update` on its own resolves to valid settings.
+pub fn validated(update: SettingsUpdate) -> Result<SettingsUpdate, String> {
+    Settings::default().apply(update.clone())?;
+    Ok(update)
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| home::home_dir().map(|home| home.join(".config")))?;
+    Some(config_home.join(USER_CONFIG_FILE))
+}
+
+/// The nearest project config file in `root` or one of its ancestors.
+pub fn find_project_config(root: &Path) -> Option<PathBuf> {
+    root.ancestors().map(|folder| folder.join(PROJECT_CONFIG_FILE)).find(|path| path.is_file())
+}
+
+fn parse_config_file(content: &str) -> Result<SettingsUpdate, String> {
+    toml::from_str(content).map_err(|e| {
+        let line = e.span().map_or(1, |span| content[..span.start].matches('\n').count() + 1);
+        format!("{}: {}", line, e.message())
+    })
+}
+
+/// Reads a TOML config file. Errors are reported as `path:line: message`.
+pub fn load_config_file(path: &Path) -> Result<SettingsUpdate, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut update = parse_config_file(&content).map_err(|e| format!("{}:{}", path.display(), e))?;
+    if let Some(base) = path.parent() {
+        update.resolve_paths(base);
+    }
+    validated(update).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_section(value: &Value) -> Result<SettingsUpdate, String> {
+    if value.is_null() {
+        return Ok(SettingsUpdate::default());
+    }
+    SettingsUpdate::deserialize(value).map_err(|e| format!("invalid settings: {}", e))
+}
+
+/// Reads the settings from `initializationOptions`, either as a flat object or
+/// nested under the `basecode` section.
+pub fn parse_settings_update(value: &Value) -> Result<SettingsUpdate, String> {
+    parse_section(value.get(SETTINGS_SECTION).unwrap_or(value))
+}
+
+/// Reads the settings from `didChangeConfiguration`. Clients may send the
+/// sections of other servers there too, so only the `basecode` section is
+/// read and payloads without it change nothing.
+pub fn parse_configuration_change(value: &Value) -> Result<SettingsUpdate, String> {
+    value.get(SETTINGS_SECTION).map_or(Ok(SettingsUpdate::default()), parse_section)
+}
+
+impl Settings {
+    pub fn apply(&self, update: SettingsUpdate) -> Result<Settings, String> {
+        let settings = self.merge(update);
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Like `apply`, without validating the result.
+    pub fn merge(&self, update: SettingsUpdate) -> Settings {
+        let mut settings = self.clone();
+        if update.snippet_folder.is_some() {
+            settings.snippet_folder = update.snippet_folder;
+        }
+        if update.root_folder.is_some() {
+            settings.root_folder = update.root_folder;
+        }
+        if update.dictionary.is_some() {
+            settings.dictionary = update.dictionary;
+        }
+        if let Some(markers) = update.markers {
+            settings.markers = markers;
+        }
+        if let Some(marker_severity) = update.marker_severity {
+            settings.marker_severity = marker_severity;
+        }
+        if let Some(symbol_patterns) = update.symbol_patterns {
+            settings.symbol_patterns = symbol_patterns;
+        }
+        if let Some(snippet_folders) = update.snippet_folders {
+            settings.snippet_folders = snippet_folders;
+        }
+        settings.source_priorities.extend(update.source_priorities.unwrap_or_default());
+        settings.languages.extend(update.languages.unwrap_or_default());
+        settings.min_word_len = update.min_word_len.unwrap_or(settings.min_word_len);
+        settings.word_source = update.word_source.unwrap_or(settings.word_source);
+        settings.tmux_source = update.tmux_source.unwrap_or(settings.tmux_source);
+        settings.tmux_interval = update.tmux_interval.unwrap_or(settings.tmux_interval);
+        settings.tmux_debounce = update.tmux_debounce.unwrap_or(settings.tmux_debounce);
+        settings.command_source = update.command_source.unwrap_or(settings.command_source);
+        settings.snippet_source = update.snippet_source.unwrap_or(settings.snippet_source);
+        settings.file_source = update.file_source.unwrap_or(settings.file_source);
+        settings.tags_source = update.tags_source.unwrap_or(settings.tags_source);
+        settings.index_workspace = update.index_workspace.unwrap_or(settings.index_workspace);
+        settings.spell_check = update.spell_check.unwrap_or(settings.spell_check);
+        settings.marker_check = update.marker_check.unwrap_or(settings.marker_check);
+        settings
+    }
+
+    /// The main snippet folder followed by the additional ones.
+    pub fn all_snippet_folders(&self) -> Vec<String> {
+        self.snippet_folder.iter().chain(self.snippet_folders.iter()).cloned().collect()
+    }
+
+    pub fn source_priority(&self, source: &str) -> i32 {
+        self.source_priorities.get(source).copied().unwrap_or_default()
+    }
+
+    /// A key that sorts the items of higher priority sources first.
+    pub fn priority_sort_prefix(&self, source: &str) -> String {
+        format!("{:05}", (PRIORITY_OFFSET - self.source_priority(source)).clamp(0, 2 * PRIORITY_OFFSET))
+    }
+
+    /// The settings with the overrides of every language matching `file_uri`.
+    pub fn for_document(&self, file_uri: &str) -> Settings {
+        let mut settings = self.clone();
+        for (name, language) in self.languages.iter() {
+            if language.matches(name, file_uri) {
+                language.apply_to(&mut settings);
+            }
+        }
+        settings
+    }
+
+    /// Whether any document is spell checked.
+    pub fn spell_check_enabled(&self) -> bool {
+        self.spell_check || self.languages.values().any(|language| language.spell_check == Some(true))
+    }
+
+    /// Whether any document can have diagnostics.
+    pub fn diagnostics_enabled(&self) -> bool {
+        self.spell_check_enabled() || self.marker_check || self.languages.values().any(|language| language.marker_check == Some(true))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        if self.min_word_len == 0 {
+            errors.push("min_word_len must be at least 1".to_string());
+        }
+        let folders = [("snippet_folder", &self.snippet_folder), ("root_folder", &self.root_folder)]
+            .into_iter()
+            .filter_map(|(name, folder)| folder.as_ref().map(|folder| (name, folder)))
+            .chain(self.snippet_folders.iter().map(|folder| ("snippet_folders", folder)));
+        for (name, folder) in folders {
+            if !Path::new(folder).is_dir() {
+                errors.push(format!("{} {} is not a directory", name, folder));
+            }
+        }
+        let priorities = self
+            .source_priorities
+            .keys()
+            .chain(self.languages.values().flat_map(|language| language.source_priorities.keys()));
+        for source in priorities {
+            if !SOURCES.contains(&source.as_str()) {
+                errors.push(format!("unknown source {} in source_priorities", source));
+            }
+        }
+        if let Some(dictionary) = &self.dictionary {
+            if !Path::new(dictionary).is_file() {
+                errors.push(format!("dictionary {} is not a file", dictionary));
+            }
+        }
+        if parse_severity(&self.marker_severity).is_none() {
+            errors.push(format!("unknown marker_severity {}", self.marker_severity));
+        }
+        for config in self.symbol_patterns.iter() {
+            if let Err(e) = SymbolPattern::from_config(config) {
+                errors.push(e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_settings_update() {
+        let update = parse_settings_update(&json!({"min_word_len": 3, "tmux_source": false})).unwrap();
+        assert_eq!(Some(3), update.min_word_len);
+        assert_eq!(Some(false), update.tmux_source);
+        assert_eq!(None, update.command_source);
+
+        let update = parse_settings_update(&json!({"basecode": {"command_source": true}})).unwrap();
+        assert_eq!(Some(true), update.command_source);
+
+        assert!(parse_settings_update(&Value::Null).is_ok());
+        assert!(parse_settings_update(&json!({"min_word_len": "three"})).is_err());
+        assert!(parse_settings_update(&json!({"unknown_option": true})).is_err());
+    }
+
+    #[test]
+    fn test_parse_configuration_change() {
+        let update = parse_configuration_change(&json!({"basecode": {"min_word_len": 3}, "rust-analyzer": {"check": {}}})).unwrap();
+        assert_eq!(Some(3), update.min_word_len);
+
+        let update = parse_configuration_change(&json!({"rust-analyzer": {"cargo": {"features": "all"}}})).unwrap();
+        assert_eq!(None, update.min_word_len);
+        assert!(parse_configuration_change(&json!({"min_word_len": 3})).unwrap().min_word_len.is_none());
+        assert!(parse_configuration_change(&Value::Null).is_ok());
+        assert!(parse_configuration_change(&json!({"basecode": {"unknown_option": true}})).is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let settings = Settings::default();
+        let update = parse_settings_update(&json!({"min_word_len": 4, "file_source": false})).unwrap();
+        let updated = settings.apply(update).unwrap();
+        assert_eq!(4, updated.min_word_len);
+        assert!(!updated.file_source);
+        assert_eq!(settings.tmux_source, updated.tmux_source);
+    }
+
+    #[test]
+    fn test_apply_validation() {
+        let settings = Settings::default();
+        let update = parse_settings_update(&json!({"min_word_len": 0})).unwrap();
+        assert!(settings.apply(update).is_err());
+
+        let update = parse_settings_update(&json!({"snippet_folder": "doesnt_exist"})).unwrap();
+        assert!(settings.apply(update).is_err());
+
+        let update = parse_settings_update(&json!({"marker_severity": "loud"})).unwrap();
+        assert!(settings.apply(update).is_err());
+
+        let update = parse_settings_update(&json!({"symbol_patterns": [{"files": [".rs"], "pattern": "fn", "kind": "function"}]})).unwrap();
+        assert!(settings.apply(update).is_err());
+
+        let update = parse_settings_update(&json!({"source_priorities": {"spell": 1}})).unwrap();
+        assert!(settings.apply(update).is_err());
+
+        let update = parse_settings_update(&json!({"root_folder": "./src"})).unwrap();
+        assert_eq!(Some("./src".to_string()), settings.apply(update).unwrap().root_folder);
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let layers = ConfigLayers {
+            user: parse_settings_update(&json!({"min_word_len": 3, "tmux_source": false, "source_priorities": {"word": 1}})).unwrap(),
+            project: parse_settings_update(&json!({"min_word_len": 4, "source_priorities": {"tmux": 2}})).unwrap(),
+            client: parse_settings_update(&json!({"min_word_len": 5, "command_source": true})).unwrap(),
+            cli: parse_settings_update(&json!({"command_source": false})).unwrap(),
+            overrides: SettingsUpdate::default(),
+        };
+        let settings = layers.resolve().unwrap();
+        assert_eq!(5, settings.min_word_len);
+        assert!(!settings.tmux_source);
+        assert!(!settings.command_source);
+        assert_eq!(1, settings.source_priority("word"));
+        assert_eq!(2, settings.source_priority("tmux"));
+        assert_eq!(0, settings.source_priority("file"));
+        assert!(settings.priority_sort_prefix("tmux") < settings.priority_sort_prefix("file"));
+    }
+
+    #[test]
+    fn test_config_layers_resolve_valid() {
+        let mut layers = ConfigLayers {
+            user: parse_settings_update(&json!({"min_word_len": 3})).unwrap(),
+            project: parse_settings_update(&json!({"min_word_len": 0, "tmux_source": false})).unwrap(),
+            client: parse_settings_update(&json!({"snippet_folder": "doesnt_exist"})).unwrap(),
+            cli: parse_settings_update(&json!({"command_source": true})).unwrap(),
+            overrides: SettingsUpdate::default(),
+        };
+        let (settings, errors) = layers.resolve_valid();
+        assert_eq!(3, settings.min_word_len);
+        assert!(settings.tmux_source);
+        assert!(settings.command_source);
+        assert_eq!(None, settings.snippet_folder);
+        assert_eq!(2, errors.len());
+        assert!(errors[0].starts_with("project settings ignored: "), "{}", errors[0]);
+        assert!(errors[1].starts_with("client settings ignored: "), "{}", errors[1]);
+        assert_eq!(None, layers.project.min_word_len);
+        assert_eq!(settings, layers.resolve().unwrap());
+    }
+
+    #[test]
+    fn test_config_layers_update() {
+        let mut layers = ConfigLayers {
+            client: parse_settings_update(&json!({"min_word_len": 5})).unwrap(),
+            ..ConfigLayers::default()
+        };
+        let previous = layers.resolve().unwrap();
+        // an invalid didChangeConfiguration leaves the settings as they were
+        let update = parse_configuration_change(&json!({"basecode": {"min_word_len": 0, "tmux_source": false}})).unwrap();
+        assert!(layers.update(|config| config.client = config.client.clone().merge(update)).is_err());
+        assert_eq!(previous, layers.resolve().unwrap());
+        assert_eq!(Some(5), layers.client.min_word_len);
+        assert_eq!(None, layers.client.tmux_source);
+
+        let update = parse_configuration_change(&json!({"basecode": {"tmux_source": false}})).unwrap();
+        let settings = layers.update(|config| config.client = config.client.clone().merge(update)).unwrap();
+        assert_eq!(5, settings.min_word_len);
+        assert!(!settings.tmux_source);
+
+        assert!(validated(parse_settings_update(&json!({"min_word_len": 0})).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_for_document() {
+        let update = parse_settings_update(&json!({
+            "spell_check": false,
+            "languages": {
+                "python": {"spell_check": true, "source_priorities": {"snippet": 3}},
+                "notes": {"files": [".txt"], "word_source": false}
+            }
+        }))
+        .unwrap();
+        let settings = Settings::default().apply(update).unwrap();
+        assert!(settings.diagnostics_enabled());
+
+        let python = settings.for_document("file:///src/main.py");
+        assert!(python.spell_check);
+        assert_eq!(3, python.source_priority("snippet"));
+        assert!(python.word_source);
+
+        let notes = settings.for_document("file:///notes/todo.txt");
+        assert!(!notes.word_source);
+        assert!(!notes.spell_check);
+    }
+
+    #[test]
+    fn test_parse_config_file() {
+        let content = "min_word_len = 3\nsnippet_folders = [\"snippets\"]\n\n[languages.rust]\nword_source = false\n";
+        let update = parse_config_file(content).unwrap();
+        assert_eq!(Some(3), update.min_word_len);
+        assert_eq!(Some(false), update.languages.unwrap()["rust"].word_source);
+
+        let error = parse_config_file("min_word_len = 3\n\nunknown_option = true\n").unwrap_err();
+        assert!(error.starts_with("3: "), "{}", error);
+        let error = parse_config_file("min_word_len = \"three\"\n").unwrap_err();
+        assert!(error.starts_with("1: "), "{}", error);
+    }
+
+    #[test]
+    fn test_load_config_file() {
+        let folder = env::temp_dir().join("basecode-lsp-config-test");
+        fs::create_dir_all(folder.join("snippets")).unwrap();
+        let path = folder.join(PROJECT_CONFIG_FILE);
+        fs::write(&path, "snippet_folder = \"snippets\"\n").unwrap();
+        let update = load_config_file(&path).unwrap();
+        assert_eq!(Some(folder.join("snippets").display().to_string()), update.snippet_folder);
+        assert_eq!(Some(path.clone()), find_project_config(&folder.join("snippets")));
+
+        fs::write(&path, "snippet_folder = \"missing\"\n").unwrap();
+        let error = load_config_file(&path).unwrap_err();
+        assert!(error.starts_with(&path.display().to_string()), "{}", error);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
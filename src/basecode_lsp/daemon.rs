@@ -0,0 +1,255 @@
+use super::config::Settings;
+use super::snippet::SnippetStores;
+use super::trie::WordIndex;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+pub const DAEMON_SOCKET: &str = "basecode-lsp.sock";
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 600;
+const CONNECT_ATTEMPTS: usize = 50;
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// State a daemon shares between the sessions of all its clients. Documents
+/// and settings stay per session: each session indexes words under its own
+/// id, and snippets are shared between sessions with the same folders.
+#[derive(Debug, Clone)]
+pub struct SharedState {
+    pub daemon: bool,
+    pub trie: Arc<Mutex<WordIndex>>,
+    pub snippets: SnippetStores,
+    pub tmux_source: Arc<Mutex<Vec<String>>>,
+    pub tmux_trigger: Arc<Notify>,
+    /// The settings of the session that triggered the tmux task last.
+    pub tmux_settings: Arc<Mutex<Settings>>,
+    pub tmux_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    session_ids: Arc<AtomicUsize>,
+    sessions: Arc<AtomicUsize>,
+    idle: Arc<Notify>,
+}
+
+impl SharedState {
+    pub fn new() -> Self {
+        SharedState {
+            daemon: false,
+            trie: Arc::new(Mutex::new(WordIndex::new())),
+            snippets: SnippetStores::default(),
+            tmux_source: Arc::new(Mutex::new(Vec::new())),
+            tmux_trigger: Arc::new(Notify::new()),
+            tmux_settings: Arc::new(Mutex::new(Settings::default())),
+            tmux_task: Arc::new(Mutex::new(None)),
+            session_ids: Arc::new(AtomicUsize::new(0)),
+            sessions: Arc::new(AtomicUsize::new(0)),
+            idle: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn daemon() -> Self {
+        SharedState {
+            daemon: true,
+            ..SharedState::new()
+        }
+    }
+
+    /// Counts a connected client until the returned guard is dropped.
+    pub fn session(&self) -> Session {
+        self.sessions.fetch_add(1, Ordering::SeqCst);
+        Session { state: self.clone() }
+    }
+
+    /// A new id for a session to index its words under.
+    pub fn next_session_id(&self) -> usize {
+        self.session_ids.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.sessions.load(Ordering::SeqCst)
+    }
+
+    /// Resolves when the last client disconnected.
+    pub async fn idle(&self) {
+        self.idle.notified().await
+    }
+}
+
+impl Default for SharedState {
+    fn default() -> Self {
+        SharedState::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct Session {
+    state: SharedState,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.state.sessions.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.state.idle.notify_one();
+        }
+    }
+}
+
+/// The per-user socket of the daemon, in `XDG_RUNTIME_DIR` when it is set.
+/// Otherwise it goes in a private directory under the shared temp dir, which
+/// is created when missing.
+pub fn daemon_socket_path() -> io::Result<PathBuf> {
+    match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).filter(|path| path.is_dir()) {
+        Some(runtime_dir) => Ok(runtime_dir.join(DAEMON_SOCKET)),
+        None => {
+            let dir = env::temp_dir().join(format!("basecode-lsp-{}", current_uid()));
+            private_dir(&dir)?;
+            Ok(dir.join(DAEMON_SOCKET))
+        }
+    }
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+/// Creates `dir` readable by the current user only, or checks that an
+/// existing one is a directory nobody else owns or can access.
+fn private_dir(dir: &Path) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        Err(_) => {}
+    }
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != current_uid() || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a private directory of the current user", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Refuses a socket another user created, which could impersonate the daemon.
+fn check_socket_owner(socket: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(socket) {
+        Ok(metadata) if metadata.uid() != current_uid() => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is owned by another user", socket.display()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Arguments the daemon is started with: the shim's own arguments without
+/// `--daemon`, serving the shared socket.
+pub fn daemon_args(args: &[String], socket: &Path) -> Vec<String> {
+    let mut daemon_args: Vec<String> = args.iter().filter(|arg| *arg != "--daemon").cloned().collect();
+    daemon_args.extend(["--listen".to_string(), format!("unix:{}", socket.display()), "--shared".to_string()]);
+    daemon_args
+}
+
+fn spawn_daemon(socket: &Path) -> io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    Command::new(env::current_exe()?)
+        .args(daemon_args(&args, socket))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // keep running when the editor that started it exits
+        .process_group(0)
+        .spawn()?;
+    Ok(())
+}
+
+/// Connects to the daemon at `socket`, starting it when none is running.
+pub async fn connect_daemon(socket: &Path) -> io::Result<UnixStream> {
+    check_socket_owner(socket)?;
+    if let Ok(stream) = UnixStream::connect(socket).await {
+        return Ok(stream);
+    }
+    spawn_daemon(socket)?;
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "daemon did not start");
+    for _ in 0..CONNECT_ATTEMPTS {
+        tokio::time::sleep(CONNECT_RETRY_INTERVAL).await;
+        check_socket_owner(socket)?;
+        match UnixStream::connect(socket).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Forwards stdin to the daemon and its replies to stdout until either side
+/// closes the connection.
+pub async fn run_shim(stream: UnixStream) -> io::Result<()> {
+    let (mut input, mut output) = stream.into_split();
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = tokio::io::stdout();
+    tokio::select! {
+        result = tokio::io::copy(&mut stdin, &mut output) => result.map(|_| ()),
+        result = tokio::io::copy(&mut input, &mut stdout) => result.map(|_| ()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daemon_args() {
+        let args = vec!["--daemon".to_string(), "--min-word-len".to_string(), "3".to_string()];
+        let socket = Path::new("/run/user/1000/basecode-lsp.sock");
+        assert_eq!(
+            vec!["--min-word-len", "3", "--listen", "unix:/run/user/1000/basecode-lsp.sock", "--shared"],
+            daemon_args(&args, socket)
+        );
+    }
+
+    #[test]
+    fn test_private_dir() {
+        let dir = env::temp_dir().join("basecode-lsp-private-dir-test");
+        let _ = fs::remove_dir_all(&dir);
+        private_dir(&dir).unwrap();
+        assert_eq!(0o700, fs::metadata(&dir).unwrap().permissions().mode() & 0o777);
+        // an existing private directory is reused
+        private_dir(&dir).unwrap();
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, private_dir(&dir).unwrap_err().kind());
+        fs::remove_dir_all(&dir).unwrap();
+
+        fs::write(&dir, "").unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, private_dir(&dir).unwrap_err().kind());
+        fs::remove_file(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let state = SharedState::daemon();
+        let first = state.session();
+        let second = state.session();
+        assert_eq!(2, state.session_count());
+        drop(first);
+        assert_eq!(1, state.session_count());
+        drop(second);
+        assert_eq!(0, state.session_count());
+        // the notification is kept until someone waits for it
+        tokio::time::timeout(Duration::from_secs(1), state.idle()).await.unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+use super::encoding::PositionEncoding;
+use tower_lsp::lsp_types::Position;
+
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    text: String,
+    line_starts: Vec<usize>,
+}
+
+fn compute_line_starts(text: &str) -> Vec<usize> {
+    let bytes = text.as_bytes();
+    let mut starts = vec![0];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => {
+                starts.push(i + 2);
+                i += 2;
+            }
+            b'\r' | b'\n' => {
+                starts.push(i + 1);
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    starts
+}
+
+impl Document {
+    pub fn new(text: String) -> Self {
+        let line_starts = compute_line_starts(&text);
+        Document { text, line_starts }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Byte range of line `n`, excluding its line terminator.
+    pub fn line_range(&self, n: usize) -> Option<(usize, usize)> {
+        let start = *self.line_starts.get(n)?;
+        let mut end = self.line_starts.get(n + 1).copied().unwrap_or(self.text.len());
+        let line = &self.text.as_bytes()[start..end];
+        if line.ends_with(b"\r\n") {
+            end -= 2;
+        } else if line.ends_with(b"\n") || line.ends_with(b"\r") {
+            end -= 1;
+        }
+        Some((start, end))
+    }
+
+    pub fn line(&self, n: usize) -> Option<&str> {
+        let (start, end) = self.line_range(n)?;
+        Some(&self.text[start..end])
+    }
+
+    pub fn offset_to_position(&self, offset: usize, encoding: PositionEncoding) -> Position {
+        let offset = offset.min(self.text.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let (start, end) = self.line_range(line).unwrap_or((offset, offset));
+        let character = encoding.len(&self.text[start..offset.clamp(start, end)]);
+        Position::new(line as u32, character)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(document: &Document) -> Vec<&str> {
+        (0..document.line_count()).filter_map(|n| document.line(n)).collect()
+    }
+
+    #[test]
+    fn test_lines() {
+        let document = Document::new("a\r\nb\nc\rd".to_string());
+        assert_eq!(4, document.line_count());
+        assert_eq!(vec!["a", "b", "c", "d"], lines(&document));
+        assert_eq!(None, document.line(4));
+
+        let document = Document::new("a\r\n".to_string());
+        assert_eq!(vec!["a", ""], lines(&document));
+
+        let document = Document::new(String::new());
+        assert_eq!(vec![""], lines(&document));
+    }
+
+    #[test]
+    fn test_offset_to_position() {
+        let document = Document::new("ab\r\nçd\n\nef".to_string());
+        let encoding = PositionEncoding::Utf16;
+        assert_eq!(Position::new(0, 0), document.offset_to_position(0, encoding));
+        assert_eq!(Position::new(0, 2), document.offset_to_position(3, encoding));
+        assert_eq!(Position::new(1, 1), document.offset_to_position(6, encoding));
+        assert_eq!(Position::new(1, 2), document.offset_to_position(6, PositionEncoding::Utf8));
+        assert_eq!(Position::new(2, 0), document.offset_to_position(8, encoding));
+        assert_eq!(Position::new(3, 2), document.offset_to_position(11, encoding));
+    }
+}
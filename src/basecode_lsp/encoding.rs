@@ -0,0 +1,128 @@
+use tower_lsp::lsp_types::PositionEncodingKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the first encoding offered by the client, falling back to the
+    /// UTF-16 default mandated by the specification.
+    pub fn negotiate(offered: Option<&Vec<PositionEncodingKind>>) -> Self {
+        offered
+            .into_iter()
+            .flatten()
+            .find_map(Self::from_kind)
+            .unwrap_or_default()
+    }
+
+    fn from_kind(kind: &PositionEncodingKind) -> Option<Self> {
+        if *kind == PositionEncodingKind::UTF8 {
+            Some(PositionEncoding::Utf8)
+        } else if *kind == PositionEncodingKind::UTF16 {
+            Some(PositionEncoding::Utf16)
+        } else if *kind == PositionEncodingKind::UTF32 {
+            Some(PositionEncoding::Utf32)
+        } else {
+            None
+        }
+    }
+
+    pub fn kind(&self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    fn char_len(&self, ch: char) -> u32 {
+        match self {
+            PositionEncoding::Utf8 => ch.len_utf8() as u32,
+            PositionEncoding::Utf16 => ch.len_utf16() as u32,
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+
+    /// Length of `text` in code units of this encoding.
+    pub fn len(&self, text: &str) -> u32 {
+        match self {
+            PositionEncoding::Utf8 => text.len() as u32,
+            _ => text.chars().map(|ch| self.char_len(ch)).sum(),
+        }
+    }
+
+    /// Column of the byte offset `byte` within `line`.
+    pub fn column(&self, line: &str, byte: usize) -> u32 {
+        self.len(&line[..byte.min(line.len())])
+    }
+
+    /// Byte offset of `column` within `line`. Columns past the end of the line
+    /// clamp to its length, and columns inside a character round down.
+    pub fn byte_offset(&self, line: &str, column: u32) -> usize {
+        let mut units = 0;
+        for (i, ch) in line.char_indices() {
+            let next = units + self.char_len(ch);
+            if next > column {
+                return i;
+            }
+            units = next;
+        }
+        line.len()
+    }
+
+    pub fn char_index(&self, line: &str, column: u32) -> usize {
+        line[..self.byte_offset(line, column)].chars().count()
+    }
+
+    pub fn char_column(&self, line: &str, char_index: usize) -> u32 {
+        let byte = line.char_indices().nth(char_index).map_or(line.len(), |(i, _)| i);
+        self.column(line, byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(PositionEncoding::Utf16, PositionEncoding::negotiate(None));
+        let offered = vec![PositionEncodingKind::UTF8, PositionEncodingKind::UTF16];
+        assert_eq!(PositionEncoding::Utf8, PositionEncoding::negotiate(Some(&offered)));
+        let offered = vec![PositionEncodingKind::new("utf-7"), PositionEncodingKind::UTF32];
+        assert_eq!(PositionEncoding::Utf32, PositionEncoding::negotiate(Some(&offered)));
+        assert_eq!(PositionEncoding::Utf16, PositionEncoding::negotiate(Some(&Vec::new())));
+    }
+
+    #[test]
+    fn test_column() {
+        let line = "a😀b中c";
+        assert_eq!(5, PositionEncoding::Utf8.column(line, 5));
+        assert_eq!(3, PositionEncoding::Utf16.column(line, 5));
+        assert_eq!(2, PositionEncoding::Utf32.column(line, 5));
+        assert_eq!(6, PositionEncoding::Utf16.column(line, line.len()));
+    }
+
+    #[test]
+    fn test_byte_offset() {
+        let line = "a😀b中c";
+        assert_eq!(5, PositionEncoding::Utf8.byte_offset(line, 5));
+        assert_eq!(5, PositionEncoding::Utf16.byte_offset(line, 3));
+        assert_eq!(1, PositionEncoding::Utf16.byte_offset(line, 2));
+        assert_eq!(5, PositionEncoding::Utf32.byte_offset(line, 2));
+        assert_eq!(line.len(), PositionEncoding::Utf32.byte_offset(line, 42));
+    }
+
+    #[test]
+    fn test_char_index() {
+        let line = "中文 word";
+        assert_eq!(3, PositionEncoding::Utf8.char_index(line, 7));
+        assert_eq!(3, PositionEncoding::Utf16.char_index(line, 3));
+        assert_eq!(3, PositionEncoding::Utf16.char_column(line, 3));
+        assert_eq!(7, PositionEncoding::Utf8.char_column(line, 3));
+    }
+}
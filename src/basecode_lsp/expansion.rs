@@ -0,0 +1,350 @@
+use hashbrown::HashMap;
+
+const SELECTED_TEXT: &str = "TM_SELECTED_TEXT";
+// a backslash before anything else is a literal backslash
+const ESCAPABLE: [char; 5] = ['\\', '$', '`', '{', '}'];
+const LSP_ESCAPABLE: [char; 3] = ['\\', '$', '}'];
+const CHOICE_ESCAPABLE: [char; 4] = ['\\', '$', ',', '|'];
+
+/// A parsed SnipMate or LSP snippet body.
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Tabstop(u32, Vec<Part>),
+    Choice(u32, Vec<String>),
+    Selection(Vec<Part>),
+    /// Only in LSP bodies.
+    Variable(String, Vec<Part>),
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    /// Whether the body is in the LSP snippet syntax rather than SnipMate's.
+    lsp: bool,
+}
+
+impl Parser {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.index + offset).copied()
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars().enumerate().all(|(i, ch)| self.peek(i) == Some(ch))
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.index;
+        while self.peek(0).is_some_and(|ch| ch.is_ascii_digit()) {
+            self.index += 1;
+        }
+        self.chars[start..self.index].iter().collect::<String>().parse().ok()
+    }
+
+    fn name(&mut self) -> Option<String> {
+        if !self.peek(0).is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_') {
+            return None;
+        }
+        let start = self.index;
+        while self.peek(0).is_some_and(|ch| ch.is_ascii_alphanumeric() || ch == '_') {
+            self.index += 1;
+        }
+        Some(self.chars[start..self.index].iter().collect())
+    }
+
+    /// Skips the `/regex/format/options}` rest of a transform, which plain
+    /// text has no use for.
+    fn skip_transform(&mut self) -> Option<()> {
+        self.index += 1;
+        let mut slashes = 0;
+        let mut depth = 0;
+        while slashes < 2 {
+            match self.peek(0)? {
+                '\\' => self.index += 1,
+                '$' if slashes == 1 && self.peek(1) == Some('{') => {
+                    depth += 1;
+                    self.index += 1;
+                }
+                '}' if depth > 0 => depth -= 1,
+                '/' if depth == 0 => slashes += 1,
+                _ => {}
+            }
+            self.index += 1;
+        }
+        while self.peek(0)? != '}' {
+            self.index += 1;
+        }
+        self.index += 1;
+        Some(())
+    }
+
+    /// Parses until the end of the input, or the `}` closing a placeholder
+    /// when `nested`.
+    fn parts(&mut self, nested: bool) -> Vec<Part> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        while let Some(ch) = self.peek(0) {
+            if ch == '}' && nested {
+                self.index += 1;
+                break;
+            }
+            let start = self.index;
+            match self.placeholder() {
+                Some(part) => {
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part);
+                }
+                None => {
+                    self.index = start;
+                    let escapable: &[char] = if self.lsp { &LSP_ESCAPABLE } else { &ESCAPABLE };
+                    if ch == '\\' && self.peek(1).is_some_and(|next| escapable.contains(&next)) {
+                        self.index += 1;
+                    }
+                    text.extend(self.peek(0));
+                    self.index += 1;
+                }
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        parts
+    }
+
+    fn placeholder(&mut self) -> Option<Part> {
+        if self.peek(0) != Some('$') {
+            return None;
+        }
+        self.index += 1;
+        if self.peek(0).is_some_and(|ch| ch.is_ascii_digit()) {
+            return Some(Part::Tabstop(self.number()?, Vec::new()));
+        }
+        if self.lsp {
+            if let Some(name) = self.name() {
+                return Some(Part::Variable(name, Vec::new()));
+            }
+        }
+        if self.peek(0) != Some('{') {
+            return None;
+        }
+        self.index += 1;
+        if self.lsp {
+            if let Some(name) = self.name() {
+                let next = self.peek(0)?;
+                self.index += 1;
+                return match next {
+                    '}' => Some(Part::Variable(name, Vec::new())),
+                    ':' => Some(Part::Variable(name, self.parts(true))),
+                    '/' => {
+                        self.index -= 1;
+                        self.skip_transform()?;
+                        Some(Part::Variable(name, Vec::new()))
+                    }
+                    _ => None,
+                };
+            }
+        } else if self.starts_with("VISUAL") {
+            self.index += "VISUAL".len();
+            return match self.peek(0)? {
+                '}' => {
+                    self.index += 1;
+                    Some(Part::Selection(Vec::new()))
+                }
+                ':' => {
+                    self.index += 1;
+                    Some(Part::Selection(self.parts(true)))
+                }
+                _ => None,
+            };
+        }
+        let number = self.number()?;
+        match self.peek(0)? {
+            '}' => {
+                self.index += 1;
+                Some(Part::Tabstop(number, Vec::new()))
+            }
+            ':' => {
+                self.index += 1;
+                Some(Part::Tabstop(number, self.parts(true)))
+            }
+            // a transformed mirror
+            '/' if self.lsp => {
+                self.skip_transform()?;
+                Some(Part::Tabstop(number, Vec::new()))
+            }
+            '|' => {
+                self.index += 1;
+                let mut options = vec![String::new()];
+                loop {
+                    match self.peek(0)? {
+                        '|' if self.peek(1) == Some('}') => {
+                            self.index += 2;
+                            return Some(Part::Choice(number, options));
+                        }
+                        ',' => options.push(String::new()),
+                        '\\' if self.peek(1).is_some_and(|next| CHOICE_ESCAPABLE.contains(&next)) => {
+                            self.index += 1;
+                            options.last_mut()?.extend(self.peek(0));
+                        }
+                        ch => options.last_mut()?.push(ch),
+                    }
+                    self.index += 1;
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn parse(body: &str, lsp: bool) -> Vec<Part> {
+    let mut parser = Parser {
+        chars: body.chars().collect(),
+        index: 0,
+        lsp,
+    };
+    parser.parts(false)
+}
+
+fn escape(text: &str, special: &[char]) -> String {
+    let mut result = String::new();
+    for ch in text.chars() {
+        if ch == '\\' || special.contains(&ch) {
+            result.push('\\');
+        }
+        result.push(ch);
+    }
+    result
+}
+
+fn render_lsp(parts: &[Part], result: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => result.push_str(&escape(text, &['$', '}'])),
+            Part::Tabstop(number, children) if children.is_empty() => result.push_str(&format!("${}", number)),
+            Part::Tabstop(number, children) => {
+                result.push_str(&format!("${{{}:", number));
+                render_lsp(children, result);
+                result.push('}');
+            }
+            Part::Choice(number, options) => {
+                let options: Vec<String> = options.iter().map(|option| escape(option, &['$', '}', ',', '|'])).collect();
+                result.push_str(&format!("${{{}|{}|}}", number, options.join(",")));
+            }
+            Part::Selection(children) if children.is_empty() => result.push_str(&format!("${{{}}}", SELECTED_TEXT)),
+            Part::Selection(children) => {
+                result.push_str(&format!("${{{}:", SELECTED_TEXT));
+                render_lsp(children, result);
+                result.push('}');
+            }
+            Part::Variable(name, children) if children.is_empty() => result.push_str(&format!("${{{}}}", name)),
+            Part::Variable(name, children) => {
+                result.push_str(&format!("${{{}:", name));
+                render_lsp(children, result);
+                result.push('}');
+            }
+        }
+    }
+}
+
+fn collect_defaults(parts: &[Part], defaults: &mut HashMap<u32, String>) {
+    for part in parts {
+        match part {
+            Part::Tabstop(number, children) if !children.is_empty() => {
+                let mut text = String::new();
+                render_plain(children, &HashMap::new(), &mut text);
+                defaults.entry(*number).or_insert(text);
+                collect_defaults(children, defaults);
+            }
+            Part::Choice(number, options) => {
+                defaults.entry(*number).or_insert(options.first().cloned().unwrap_or_default());
+            }
+            Part::Selection(children) | Part::Variable(_, children) => collect_defaults(children, defaults),
+            _ => {}
+        }
+    }
+}
+
+fn render_plain(parts: &[Part], defaults: &HashMap<u32, String>, result: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => result.push_str(text),
+            Part::Tabstop(number, children) if children.is_empty() => {
+                result.push_str(defaults.get(number).map_or("", String::as_str));
+            }
+            Part::Tabstop(_, children) | Part::Selection(children) | Part::Variable(_, children) => {
+                render_plain(children, defaults, result)
+            }
+            Part::Choice(_, options) => result.push_str(options.first().map_or("", String::as_str)),
+        }
+    }
+}
+
+/// Translates a SnipMate body into the LSP snippet syntax. Placeholders,
+/// mirrors, choices and `${VISUAL}` keep their meaning; everything else is
+/// escaped so the client inserts it literally.
+pub fn to_lsp_snippet(body: &str) -> String {
+    let mut result = String::new();
+    render_lsp(&parse(body, false), &mut result);
+    result
+}
+
+fn plain_text(parts: &[Part]) -> String {
+    let mut defaults = HashMap::new();
+    collect_defaults(parts, &mut defaults);
+    let mut result = String::new();
+    render_plain(parts, &defaults, &mut result);
+    result
+}
+
+/// The text a SnipMate body expands to without any editing: placeholders and
+/// their mirrors become their default text.
+pub fn to_plain_text(body: &str) -> String {
+    plain_text(&parse(body, false))
+}
+
+/// Like `to_plain_text` for a body in the LSP snippet syntax, as VSCode
+/// snippets are written. Variables become their default text and transforms
+/// are left out.
+pub fn lsp_to_plain_text(body: &str) -> String {
+    plain_text(&parse(body, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_lsp_snippet() {
+        assert_eq!("for (${1:i} = 0; $1 < ${2:n}; $1++) {\n  $0\n\\}", to_lsp_snippet("for (${1:i} = 0; $1 < ${2:n}; $1++) {\n  $0\n}"));
+        assert_eq!("${1:outer ${2:inner}}", to_lsp_snippet("${1:outer ${2:inner}}"));
+        assert_eq!("${1|one,two\\,three|}", to_lsp_snippet("${1|one,two\\,three|}"));
+        assert_eq!("<b>${TM_SELECTED_TEXT:text}</b>", to_lsp_snippet("<b>${VISUAL:text}</b>"));
+    }
+
+    #[test]
+    fn test_to_lsp_snippet_escapes() {
+        assert_eq!("echo \\$HOME \\$", to_lsp_snippet("echo $HOME $"));
+        assert_eq!("price: \\$5", to_lsp_snippet("price: \\$5"));
+        assert_eq!("a \\\\ b", to_lsp_snippet("a \\ b"));
+        assert_eq!("\\${name\\}", to_lsp_snippet("${name}"));
+        assert_eq!("`strftime(\"%Y\")`", to_lsp_snippet("`strftime(\"%Y\")`"));
+    }
+
+    #[test]
+    fn test_to_plain_text() {
+        assert_eq!("for (i = 0; i < n; i++) {\n  \n}", to_plain_text("for (${1:i} = 0; $1 < ${2:n}; $1++) {\n  $0\n}"));
+        assert_eq!("one", to_plain_text("${1|one,two|}"));
+        assert_eq!("echo $HOME", to_plain_text("echo $HOME"));
+        assert_eq!("<b>text</b>", to_plain_text("<b>${VISUAL:text}</b>"));
+    }
+
+    #[test]
+    fn test_lsp_to_plain_text() {
+        assert_eq!("for (i = 0; i < n; i++) {\n\t\n}", lsp_to_plain_text("for (${1:i} = 0; $1 < ${2:n}; $1++) {\n\t$0\n}"));
+        assert_eq!("one 2024 ", lsp_to_plain_text("${1|one,two|} ${CURRENT_YEAR:2024} $TM_FILENAME"));
+        assert_eq!("name name.", lsp_to_plain_text("${1:name} ${1/(.*)/${1:/upcase}/g}${TM_FILENAME/(.*)\\..+$/$1/}."));
+        assert_eq!("$5 } \\`cmd`", lsp_to_plain_text("\\$5 \\} \\\\`cmd`"));
+    }
+}
@@ -34,24 +34,22 @@ pub fn list_all_file_items(path: &Path, pos: usize) -> Vec<FileItem> {
     let read_result = fs::read_dir(path);
     let mut result = Vec::new();
     if let Ok(entries) = read_result {
-        for item in entries {
-            if let Ok(entry) = item {
-                let current = entry.path();
-                let filename = current.file_name().unwrap_or(OsStr::new("")).to_str();
-                if let Some(f) = filename {
-                    result.push(FileItem {
-                        filename: f.to_string(),
-                        pos,
-                        is_dir: current.is_dir(),
-                    });
-                }
+        for entry in entries.flatten() {
+            let current = entry.path();
+            let filename = current.file_name().unwrap_or(OsStr::new("")).to_str();
+            if let Some(f) = filename {
+                result.push(FileItem {
+                    filename: f.to_string(),
+                    pos,
+                    is_dir: current.is_dir(),
+                });
             }
         }
     }
     result
 }
 
-const MAX_LINE_LENGTH: usize = 600;
+pub const MAX_LINE_LENGTH: usize = 600;
 
 pub fn get_file_items(current_line: &str, root_folder: &str) -> Vec<FileItem> {
     if current_line.len() > MAX_LINE_LENGTH {
@@ -82,6 +80,20 @@ pub fn get_file_items(current_line: &str, root_folder: &str) -> Vec<FileItem> {
     file_items
 }
 
+/// Resolves `path` to an existing file or directory. `~` expands to the home
+/// directory and relative paths are tried against each of `bases` in turn.
+pub fn resolve_link_target(path: &str, bases: &[PathBuf]) -> Option<PathBuf> {
+    if let Some(rest) = path.strip_prefix("~/").or(path.strip_prefix("~\\")) {
+        let resolved = home::home_dir()?.join(rest);
+        return resolved.exists().then_some(resolved);
+    }
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return path.exists().then(|| path.to_path_buf());
+    }
+    bases.iter().map(|base| base.join(path)).find(|resolved| resolved.exists())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,6 +118,16 @@ mod tests {
         assert_eq!(0, items.len());
     }
 
+    #[test]
+    fn test_resolve_link_target() {
+        let bases = vec![PathBuf::from("doesnt_exist"), PathBuf::from("./src")];
+        assert_eq!(Some(PathBuf::from("./src/main.rs")), resolve_link_target("main.rs", &bases));
+        assert_eq!(Some(PathBuf::from("./src/basecode_lsp")), resolve_link_target("basecode_lsp", &bases));
+        assert_eq!(None, resolve_link_target("missing.rs", &bases));
+        assert_eq!(None, resolve_link_target("/doesnt_exist/main.rs", &bases));
+        assert!(resolve_link_target("/", &bases).is_some());
+    }
+
     #[test]
     fn test_get_file_items() {
         // Create a dummy directory structure for testing
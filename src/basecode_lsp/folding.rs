@@ -0,0 +1,206 @@
+use super::document::Document;
+use super::encoding::PositionEncoding;
+use super::lexer::*;
+use hashbrown::HashMap;
+use tower_lsp::lsp_types::*;
+
+const TAB_WIDTH: usize = 4;
+const MARKER_START: &str = "{{{";
+const MARKER_END: &str = "}}}";
+const REGION_START: &str = "#region";
+const REGION_END: &str = "#endregion";
+
+#[derive(Debug, Clone, Copy)]
+pub struct FoldingOptions {
+    pub line_folding_only: bool,
+    pub range_limit: Option<usize>,
+    pub encoding: PositionEncoding,
+}
+
+fn indent_width(line: &str) -> Option<usize> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    let width = line
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum();
+    Some(width)
+}
+
+fn line_range(start_line: usize, end_line: usize, kind: Option<FoldingRangeKind>) -> FoldingRange {
+    FoldingRange {
+        start_line: start_line as u32,
+        end_line: end_line as u32,
+        kind,
+        ..FoldingRange::default()
+    }
+}
+
+fn is_marker_start(line: &str) -> bool {
+    line.contains(MARKER_START) || (line.contains(REGION_START) && !line.contains(REGION_END))
+}
+
+fn is_marker_end(line: &str) -> bool {
+    line.contains(MARKER_END) || line.contains(REGION_END)
+}
+
+fn marker_ranges(document: &Document) -> Vec<FoldingRange> {
+    let mut ranges = Vec::new();
+    let mut stack = Vec::new();
+    for n in 0..document.line_count() {
+        let line = document.line(n).unwrap_or_default();
+        if is_marker_end(line) {
+            if let Some(start) = stack.pop() {
+                ranges.push(line_range(start, n, Some(FoldingRangeKind::Region)));
+            }
+        } else if is_marker_start(line) {
+            stack.push(n);
+        }
+    }
+    ranges
+}
+
+fn bracket_ranges(document: &Document, syntax: Option<&Syntax>, options: FoldingOptions) -> Vec<FoldingRange> {
+    let spans = syntax.map_or(Vec::new(), |s| non_code_spans(document.text(), s));
+    let mut ranges = Vec::new();
+    let mut stack: Vec<(char, usize, u32)> = Vec::new();
+    for n in 0..document.line_count() {
+        let (offset, end) = document.line_range(n).unwrap_or_default();
+        let line = &document.text()[offset..end];
+        if is_marker_start(line) || is_marker_end(line) {
+            continue;
+        }
+        for (byte, ch) in line.char_indices() {
+            if !matches!(ch, '{' | '[' | '(' | '}' | ']' | ')') || span_at(&spans, offset + byte).is_some() {
+                continue;
+            }
+            if matches!(ch, '{' | '[' | '(') {
+                stack.push((ch, n, options.encoding.column(line, byte + 1)));
+                continue;
+            }
+            let open = match ch {
+                '}' => '{',
+                ']' => '[',
+                _ => '(',
+            };
+            let Some(index) = stack.iter().rposition(|(c, _, _)| *c == open) else {
+                continue;
+            };
+            let (_, start_line, start_character) = stack[index];
+            stack.truncate(index);
+            if options.line_folding_only {
+                if n > start_line + 1 {
+                    ranges.push(line_range(start_line, n - 1, None));
+                }
+            } else if n > start_line {
+                ranges.push(FoldingRange {
+                    start_line: start_line as u32,
+                    start_character: Some(start_character),
+                    end_line: n as u32,
+                    end_character: Some(options.encoding.column(line, byte)),
+                    ..FoldingRange::default()
+                });
+            }
+        }
+    }
+    ranges
+}
+
+fn indentation_ranges(document: &Document) -> Vec<FoldingRange> {
+    let indents: Vec<Option<usize>> = (0..document.line_count())
+        .map(|n| indent_width(document.line(n).unwrap_or_default()))
+        .collect();
+    let mut ranges = Vec::new();
+    for (n, indent) in indents.iter().enumerate() {
+        let Some(indent) = *indent else {
+            continue;
+        };
+        let mut last = n;
+        for (m, other) in indents.iter().enumerate().skip(n + 1) {
+            match other {
+                Some(other) if *other > indent => last = m,
+                Some(_) => break,
+                None => {}
+            }
+        }
+        if last > n {
+            ranges.push(line_range(n, last, None));
+        }
+    }
+    ranges
+}
+
+/// Folding ranges from explicit markers, bracket pairs and indentation, in that
+/// order of preference when several start on the same line.
+pub fn folding_ranges(document: &Document, syntax: Option<&Syntax>, options: FoldingOptions) -> Vec<FoldingRange> {
+    let mut by_start_line: HashMap<u32, FoldingRange> = HashMap::new();
+    let candidates = marker_ranges(document)
+        .into_iter()
+        .chain(bracket_ranges(document, syntax, options))
+        .chain(indentation_ranges(document));
+    for range in candidates {
+        by_start_line.entry(range.start_line).or_insert(range);
+    }
+    let mut ranges: Vec<FoldingRange> = by_start_line.into_values().collect();
+    ranges.sort_by_key(|range| (range.start_line, range.end_line));
+    if let Some(limit) = options.range_limit {
+        ranges.truncate(limit);
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE_OPTIONS: FoldingOptions = FoldingOptions {
+        line_folding_only: true,
+        range_limit: None,
+        encoding: PositionEncoding::Utf16,
+    };
+
+    fn lines(ranges: &[FoldingRange]) -> Vec<(u32, u32)> {
+        ranges.iter().map(|range| (range.start_line, range.end_line)).collect()
+    }
+
+    #[test]
+    fn test_indentation_ranges() {
+        let document = Document::new("def f():\n    a = 1\n\n    if a:\n        b\nc".to_string());
+        assert_eq!(vec![(0, 4), (3, 4)], lines(&folding_ranges(&document, None, LINE_OPTIONS)));
+    }
+
+    #[test]
+    fn test_bracket_ranges() {
+        let document = Document::new("fn f() {\nlet s = \"{\";\nlet v = [\n1,\n];\n}".to_string());
+        let syntax = get_syntax("test.rs");
+        assert_eq!(vec![(0, 4), (2, 3)], lines(&folding_ranges(&document, syntax, LINE_OPTIONS)));
+
+        let options = FoldingOptions {
+            line_folding_only: false,
+            ..LINE_OPTIONS
+        };
+        let ranges = folding_ranges(&document, syntax, options);
+        assert_eq!(Some(8), ranges[0].start_character);
+        assert_eq!((5, Some(0)), (ranges[0].end_line, ranges[0].end_character));
+    }
+
+    #[test]
+    fn test_marker_ranges() {
+        let document = Document::new("# setup {{{\na\n# }}}\n// #region\nb\n// #endregion".to_string());
+        let ranges = folding_ranges(&document, None, LINE_OPTIONS);
+        assert_eq!(vec![(0, 2), (3, 5)], lines(&ranges));
+        assert!(ranges.iter().all(|range| range.kind == Some(FoldingRangeKind::Region)));
+    }
+
+    #[test]
+    fn test_range_limit() {
+        let document = Document::new("a\n b\n  c\n   d".to_string());
+        let options = FoldingOptions {
+            range_limit: Some(2),
+            ..LINE_OPTIONS
+        };
+        assert_eq!(vec![(0, 3), (1, 3)], lines(&folding_ranges(&document, None, options)));
+    }
+}
@@ -0,0 +1,252 @@
+use super::backend::Backend;
+use hashbrown::HashMap;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::task::Context;
+use std::task::Poll;
+use tower_lsp::jsonrpc::Request;
+use tower_lsp::jsonrpc::Response;
+use tower_lsp::lsp_types::*;
+use tower_lsp::ExitedError;
+use tower_lsp::LspService;
+use tower_service::Service;
+
+// textDocument/inlineCompletion is new in LSP 3.18 and not part of lsp-types yet
+pub const INLINE_COMPLETION_METHOD: &str = "textDocument/inlineCompletion";
+
+const MIN_LINE_PREFIX_LEN: usize = 3;
+const MAX_INLINE_ITEMS: usize = 5;
+const MAX_INDEXED_LINE_LEN: usize = 200;
+
+/// Whether the raw initialize `params` let the server register inline
+/// completion dynamically.
+pub fn inline_completion_registration(params: &Value) -> bool {
+    params
+        .pointer("/capabilities/textDocument/inlineCompletion/dynamicRegistration")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// Passes requests on to the language server, telling it the client's
+/// inlineCompletion capability, which lsp-types drops from the initialize
+/// params, before the initialize request.
+pub struct InlineCompletionService {
+    inner: LspService<Backend>,
+}
+
+impl InlineCompletionService {
+    pub fn new(inner: LspService<Backend>) -> Self {
+        InlineCompletionService { inner }
+    }
+}
+
+impl Service<Request> for InlineCompletionService {
+    type Response = Option<Response>;
+    type Error = ExitedError;
+    type Future = <LspService<Backend> as Service<Request>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        if request.method() == "initialize" {
+            let registration = request.params().is_some_and(inline_completion_registration);
+            self.inner.inner().set_inline_completion_registration(registration);
+        }
+        self.inner.call(request)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionItem {
+    pub insert_text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InlineCompletionList {
+    pub items: Vec<InlineCompletionItem>,
+}
+
+/// The lines of the indexed workspace files without their indentation, with
+/// how often each occurs, ordered so that continuations are found by prefix.
+#[derive(Debug, Default)]
+pub struct LineIndex {
+    lines: BTreeMap<String, usize>,
+}
+
+impl LineIndex {
+    pub fn add<'a>(&mut self, lines: impl Iterator<Item = &'a str>) {
+        for line in lines.map(|line| line.trim()) {
+            if line.len() > MIN_LINE_PREFIX_LEN && line.len() <= MAX_INDEXED_LINE_LEN {
+                *self.lines.entry(line.to_string()).or_default() += 1;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    fn starting_with<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, usize)> {
+        self.lines
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(line, _)| line.starts_with(prefix))
+            .map(|(line, count)| (line.as_str(), *count))
+    }
+}
+
+/// Returns the lines of the open documents and of the workspace `index` that
+/// continue `prefix`, most frequent first. `prefix` and the candidate lines
+/// are compared without their indentation.
+pub fn line_completions<'a, I>(prefix: &str, lines: I, index: &'a LineIndex) -> Vec<String>
+where
+    I: Iterator<Item = &'a str>,
+{
+    if prefix.chars().count() < MIN_LINE_PREFIX_LEN {
+        return Vec::new();
+    }
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for line in lines.map(|line| line.trim()) {
+        if line.len() > prefix.len() && line.starts_with(prefix) {
+            *counts.entry(line).or_default() += 1;
+        }
+    }
+    for (line, count) in index.starting_with(prefix) {
+        if line.len() > prefix.len() {
+            *counts.entry(line).or_default() += count;
+        }
+    }
+    let mut candidates: Vec<(&str, usize)> = counts.into_iter().collect();
+    candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    candidates
+        .into_iter()
+        .take(MAX_INLINE_ITEMS)
+        .map(|(line, _)| line.to_string())
+        .collect()
+}
+
+/// Returns the labels of the completion `items` that continue `prefix`, in
+/// the order a client sorts them: by sort text, then by label.
+pub fn word_completions(prefix: &str, mut items: Vec<CompletionItem>) -> Vec<String> {
+    items.retain(|item| item.label.len() > prefix.len() && item.label.starts_with(prefix));
+    items.sort_by(|a, b| (a.sort_text.as_ref().unwrap_or(&a.label), &a.label).cmp(&(b.sort_text.as_ref().unwrap_or(&b.label), &b.label)));
+    let mut words: Vec<String> = Vec::new();
+    for item in items {
+        if !words.contains(&item.label) {
+            words.push(item.label);
+        }
+        if words.len() == MAX_INLINE_ITEMS {
+            break;
+        }
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_completions() {
+        let lines = [
+            "    let mut result = Vec::new();",
+            "let mut result = HashMap::new();",
+            "  let mut result = Vec::new();",
+            "let mut",
+            "return result;",
+        ];
+        let index = LineIndex::default();
+        let completions = line_completions("let mut re", lines.into_iter(), &index);
+        assert_eq!(
+            vec!["let mut result = Vec::new();", "let mut result = HashMap::new();"],
+            completions
+        );
+
+        assert!(line_completions("le", lines.into_iter(), &index).is_empty());
+        assert!(line_completions("let mut result = Vec::new();", lines.into_iter(), &index).is_empty());
+    }
+
+    #[test]
+    fn test_line_completions_index() {
+        let mut index = LineIndex::default();
+        index.add(["let mut result = HashMap::new();", "  let mut result = HashMap::new();", "let mut", "let x = 1;"].into_iter());
+        index.add(["let mut result = BTreeMap::new();", "let mut resul"].into_iter());
+        let lines = ["let mut result = Vec::new();"];
+        let completions = line_completions("let mut re", lines.into_iter(), &index);
+        assert_eq!(
+            vec![
+                "let mut result = HashMap::new();",
+                "let mut resul",
+                "let mut result = BTreeMap::new();",
+                "let mut result = Vec::new();",
+            ],
+            completions
+        );
+
+        index.clear();
+        assert!(line_completions("let mut re", [].into_iter(), &index).is_empty());
+    }
+
+    #[test]
+    fn test_word_completions() {
+        let item = |label: &str, sort_text: Option<&str>| CompletionItem {
+            label: label.to_string(),
+            sort_text: sort_text.map(str::to_string),
+            ..CompletionItem::default()
+        };
+        let items = vec![
+            item("completion", None),
+            item("compile", None),
+            item("comp", None),
+            item("compile", None),
+            item("other", None),
+        ];
+        assert_eq!(vec!["compile", "completion"], word_completions("comp", items));
+
+        // a prioritized source comes first, as in regular completion
+        let items = vec![item("compile", Some("50000compile")), item("complete", Some("49990complete"))];
+        assert_eq!(vec!["complete", "compile"], word_completions("comp", items));
+    }
+
+    #[test]
+    fn test_inline_completion_registration() {
+        let params = serde_json::json!({
+            "capabilities": {"textDocument": {"inlineCompletion": {"dynamicRegistration": true}, "completion": {}}}
+        });
+        assert!(inline_completion_registration(&params));
+        // completion's dynamic registration does not stand in for it
+        let params = serde_json::json!({"capabilities": {"textDocument": {"completion": {"dynamicRegistration": true}}}});
+        assert!(!inline_completion_registration(&params));
+        assert!(!inline_completion_registration(&serde_json::json!({})));
+    }
+
+    #[test]
+    fn test_serialize_inline_completion_list() {
+        let list = InlineCompletionList {
+            items: vec![InlineCompletionItem {
+                insert_text: "compile".to_string(),
+                filter_text: None,
+                range: Some(Range::new(Position::new(0, 0), Position::new(0, 4))),
+            }],
+        };
+        let value = serde_json::to_value(list).unwrap();
+        assert_eq!("compile", value["items"][0]["insertText"]);
+        assert!(value["items"][0].get("filterText").is_none());
+    }
+}
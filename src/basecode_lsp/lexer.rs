@@ -0,0 +1,335 @@
+#[derive(Debug)]
+pub struct Syntax {
+    pub line_comments: &'static [&'static str],
+    pub block_comments: &'static [(&'static str, &'static str)],
+    pub block_strings: &'static [&'static str],
+    pub quotes: &'static [char],
+    /// Rust: `'x'` is a char literal while `'a` is a lifetime, and `r#"…"#`
+    /// is a raw string.
+    pub rust_literals: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    Comment,
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub kind: SpanKind,
+}
+
+const C_LIKE: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[("/*", "*/")],
+    block_strings: &[],
+    quotes: &['"', '\''],
+    rust_literals: false,
+};
+
+// go raw strings and javascript template literals
+const C_LIKE_BACKTICK: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[("/*", "*/")],
+    block_strings: &["`"],
+    quotes: &['"', '\''],
+    rust_literals: false,
+};
+
+// single quotes are handled by rust_literals, since most of them are lifetimes
+const RUST: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[("/*", "*/")],
+    block_strings: &[],
+    quotes: &['"'],
+    rust_literals: true,
+};
+
+const PYTHON: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comments: &[],
+    block_strings: &["\"\"\"", "'''"],
+    quotes: &['"', '\''],
+    rust_literals: false,
+};
+
+const HASH: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comments: &[],
+    block_strings: &[],
+    quotes: &['"', '\''],
+    rust_literals: false,
+};
+
+const INI: Syntax = Syntax {
+    line_comments: &["#", ";"],
+    block_comments: &[],
+    block_strings: &[],
+    quotes: &['"', '\''],
+    rust_literals: false,
+};
+
+const LUA: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comments: &[("--[[", "]]")],
+    block_strings: &["[["],
+    quotes: &['"', '\''],
+    rust_literals: false,
+};
+
+const SQL: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comments: &[("/*", "*/")],
+    block_strings: &[],
+    quotes: &['"', '\''],
+    rust_literals: false,
+};
+
+const LISP: Syntax = Syntax {
+    line_comments: &[";"],
+    block_comments: &[],
+    block_strings: &[],
+    quotes: &['"'],
+    rust_literals: false,
+};
+
+const TEX: Syntax = Syntax {
+    line_comments: &["%"],
+    block_comments: &[],
+    block_strings: &[],
+    quotes: &[],
+    rust_literals: false,
+};
+
+const MARKUP: Syntax = Syntax {
+    line_comments: &[],
+    block_comments: &[("<!--", "-->")],
+    block_strings: &[],
+    quotes: &['"'],
+    rust_literals: false,
+};
+
+const SYNTAX_PATTERNS: &[(&[&str], &Syntax)] = &[
+    (
+        &[
+            ".c", ".h", ".cc", ".cpp", ".hpp", ".java", ".kt", ".dart", ".cs", ".swift", ".scala",
+            ".proto",
+        ],
+        &C_LIKE,
+    ),
+    (&[".go", ".js", ".jsx", ".ts", ".tsx"], &C_LIKE_BACKTICK),
+    (&[".rs"], &RUST),
+    (&[".py"], &PYTHON),
+    (
+        &[
+            ".sh", ".bash", ".zsh", ".rb", ".pl", ".r", ".cmake", "CMakeLists.txt", "Makefile",
+            ".mk", ".yaml", ".yml", ".toml", ".conf", "Dockerfile",
+        ],
+        &HASH,
+    ),
+    (&[".ini", ".cfg"], &INI),
+    (&[".lua"], &LUA),
+    (&[".sql"], &SQL),
+    (&[".el", ".lisp", ".clj", ".scm"], &LISP),
+    (&[".tex"], &TEX),
+    (&[".html", ".xml", ".svg"], &MARKUP),
+];
+
+pub fn get_syntax(file_uri: &str) -> Option<&'static Syntax> {
+    for (patterns, syntax) in SYNTAX_PATTERNS.iter() {
+        if patterns.iter().any(|p| file_uri.ends_with(p)) {
+            return Some(*syntax);
+        }
+    }
+    None
+}
+
+fn find_closing_quote(text: &str, start: usize, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, ch) in text[start..].char_indices() {
+        if escaped {
+            escaped = false;
+        } else if ch == '\\' {
+            escaped = true;
+        } else if ch == quote {
+            return start + i + ch.len_utf8();
+        } else if ch == '\n' || ch == '\r' {
+            return start + i;
+        }
+    }
+    text.len()
+}
+
+/// The end of a rust char literal starting at the `'` at `start`, or `None`
+/// for a lifetime or label.
+fn rust_char_end(text: &str, start: usize) -> Option<usize> {
+    let mut chars = text[start + 1..].char_indices();
+    let (_, first) = chars.next()?;
+    if first == '\\' {
+        // escapes such as '\n', '\'' and '\u{1F600}'
+        chars.next()?;
+        let (i, _) = chars.take_while(|&(_, ch)| ch != '\n').find(|&(_, ch)| ch == '\'')?;
+        return Some(start + 1 + i + 1);
+    }
+    match chars.next() {
+        Some((i, '\'')) if first != '\'' => Some(start + 1 + i + 1),
+        _ => None,
+    }
+}
+
+/// The end of a rust raw string such as `r#"…"#` starting at `start`.
+fn rust_raw_string_end(text: &str, start: usize) -> Option<usize> {
+    let rest = text[start..].strip_prefix("br").or(text[start..].strip_prefix('r'))?;
+    let hashes = rest.len() - rest.trim_start_matches('#').len();
+    if !rest[hashes..].starts_with('"') {
+        return None;
+    }
+    let open = start + (text.len() - start - rest.len()) + hashes + 1;
+    let close = format!("\"{}", "#".repeat(hashes));
+    Some(find_end(text, open, &close))
+}
+
+fn find_end(text: &str, start: usize, end_marker: &str) -> usize {
+    match text[start..].find(end_marker) {
+        Some(index) => start + index + end_marker.len(),
+        None => text.len(),
+    }
+}
+
+/// Returns the byte spans of comments and string literals in `text`.
+pub fn non_code_spans(text: &str, syntax: &Syntax) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let mut end = None;
+
+        if let Some(&(open, close)) = syntax.block_comments.iter().find(|(open, _)| rest.starts_with(open)) {
+            end = Some((find_end(text, i + open.len(), close), SpanKind::Comment));
+        } else if syntax.line_comments.iter().any(|c| rest.starts_with(c)) {
+            let line_end = rest.find(['\n', '\r']).map_or(text.len(), |n| i + n);
+            end = Some((line_end, SpanKind::Comment));
+        } else if let Some(&delimiter) = syntax.block_strings.iter().find(|d| rest.starts_with(**d)) {
+            let close = if delimiter == "[[" { "]]" } else { delimiter };
+            end = Some((find_end(text, i + delimiter.len(), close), SpanKind::String));
+        } else if let Some(string_end) = syntax
+            .rust_literals
+            .then(|| {
+                let after_identifier = text[..i].chars().next_back().is_some_and(|ch| ch.is_alphanumeric() || ch == '_');
+                match rest.chars().next() {
+                    Some('\'') => rust_char_end(text, i),
+                    Some('r' | 'b') if !after_identifier => rust_raw_string_end(text, i),
+                    _ => None,
+                }
+            })
+            .flatten()
+        {
+            end = Some((string_end, SpanKind::String));
+        } else if let Some(&quote) = syntax.quotes.iter().find(|q| rest.starts_with(**q)) {
+            end = Some((find_closing_quote(text, i + 1, quote), SpanKind::String));
+        }
+
+        match end {
+            Some((end, kind)) => {
+                spans.push(Span { start: i, end, kind });
+                i = end;
+            }
+            None => {
+                i += rest.chars().next().map_or(1, |ch| ch.len_utf8());
+            }
+        }
+    }
+    spans
+}
+
+pub fn span_at(spans: &[Span], offset: usize) -> Option<&Span> {
+    let index = spans.partition_point(|span| span.end <= offset);
+    spans.get(index).filter(|span| span.start <= offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_syntax() {
+        assert_eq!(Some(&["//"][..]), get_syntax("file:///a/main.cpp").map(|s| s.line_comments));
+        assert_eq!(Some(&["#"][..]), get_syntax("file:///a/build.sh").map(|s| s.line_comments));
+        assert_eq!(Some(&["#"][..]), get_syntax("file:///a/Makefile").map(|s| s.line_comments));
+        assert!(get_syntax("file:///a/notes.txt").is_none());
+    }
+
+    #[test]
+    fn test_non_code_spans() {
+        let text = "int a = 1; // a comment\nchar* s = \"a \\\" b\"; /* a\nb */ a";
+        let spans = non_code_spans(text, &C_LIKE);
+        let pieces: Vec<(&str, SpanKind)> = spans.iter().map(|s| (&text[s.start..s.end], s.kind)).collect();
+        assert_eq!(
+            vec![
+                ("// a comment", SpanKind::Comment),
+                ("\"a \\\" b\"", SpanKind::String),
+                ("/* a\nb */", SpanKind::Comment),
+            ],
+            pieces
+        );
+    }
+
+    #[test]
+    fn test_non_code_spans_python() {
+        let text = "def f(a):\n    \"\"\"return a\n    \"\"\"\n    return a # a";
+        let spans = non_code_spans(text, &PYTHON);
+        assert_eq!(2, spans.len());
+        assert_eq!("\"\"\"return a\n    \"\"\"", &text[spans[0].start..spans[0].end]);
+        assert_eq!("# a", &text[spans[1].start..spans[1].end]);
+    }
+
+    #[test]
+    fn test_unterminated_quote_stops_at_line_end() {
+        let text = "x = 'abc\ny = 1";
+        let spans = non_code_spans(text, &HASH);
+        assert_eq!(1, spans.len());
+        assert_eq!("'abc", &text[spans[0].start..spans[0].end]);
+    }
+
+    #[test]
+    fn test_line_comment_stops_at_carriage_return() {
+        let text = "a // b\r\nc // d\re";
+        let spans = non_code_spans(text, &C_LIKE);
+        let pieces: Vec<&str> = spans.iter().map(|s| &text[s.start..s.end]).collect();
+        assert_eq!(vec!["// b", "// d"], pieces);
+    }
+
+    #[test]
+    fn test_non_code_spans_rust() {
+        let text = "fn f<'a>(s: &'a str) -> char { let q = '\"'; let n = '\\n'; let e = 'é'; let r = r#\"a \"b\" c\"#; 'x' }";
+        let spans = non_code_spans(text, &RUST);
+        let pieces: Vec<&str> = spans.iter().map(|s| &text[s.start..s.end]).collect();
+        assert_eq!(vec!["'\"'", "'\\n'", "'é'", "r#\"a \"b\" c\"#", "'x'"], pieces);
+        assert!(spans.iter().all(|span| span.kind == SpanKind::String));
+
+        let text = "let bar = \"x\"; 'outer: loop { break 'outer; }";
+        let pieces: Vec<&str> = non_code_spans(text, &RUST).iter().map(|s| &text[s.start..s.end]).collect();
+        assert_eq!(vec!["\"x\""], pieces);
+    }
+
+    #[test]
+    fn test_backtick_strings() {
+        assert!(get_syntax("main.c").unwrap().block_strings.is_empty());
+        let text = "s := `raw // not a comment`";
+        let spans = non_code_spans(text, get_syntax("main.go").unwrap());
+        assert_eq!(1, spans.len());
+        assert_eq!(SpanKind::String, spans[0].kind);
+    }
+
+    #[test]
+    fn test_span_at() {
+        let text = "a // b\nc";
+        let spans = non_code_spans(text, &C_LIKE);
+        assert!(span_at(&spans, 0).is_none());
+        assert!(span_at(&spans, 5).is_some());
+        assert!(span_at(&spans, 7).is_none());
+    }
+}
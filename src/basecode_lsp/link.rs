@@ -0,0 +1,112 @@
+use super::document::Document;
+use super::encoding::PositionEncoding;
+use super::file::*;
+use regex::Regex;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::*;
+
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?'];
+
+fn url_regex() -> &'static Regex {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    URL.get_or_init(|| Regex::new(r#"https?://[^\s<>"'`()\[\]{}]+"#).unwrap())
+}
+
+fn path_regex() -> &'static Regex {
+    static PATH: OnceLock<Regex> = OnceLock::new();
+    PATH.get_or_init(|| Regex::new(r"[\w~./\\@%+-]+").unwrap())
+}
+
+fn looks_like_path(token: &str) -> bool {
+    if token.chars().all(|c| c == '.' || c == '/' || c == '\\') {
+        return false;
+    }
+    token.starts_with('~') || token.contains(['/', '\\']) || token.trim_start_matches('.').contains('.')
+}
+
+fn line_links(line: &str, bases: &[PathBuf]) -> Vec<(usize, usize, Url)> {
+    let mut links = Vec::new();
+    for m in url_regex().find_iter(line) {
+        let url = m.as_str().trim_end_matches(TRAILING_PUNCTUATION);
+        if let Ok(target) = Url::parse(url) {
+            links.push((m.start(), m.start() + url.len(), target));
+        }
+    }
+    let url_ranges: Vec<(usize, usize)> = links.iter().map(|(start, end, _)| (*start, *end)).collect();
+    for m in path_regex().find_iter(line) {
+        if url_ranges.iter().any(|(start, end)| m.start() < *end && *start < m.end()) {
+            continue;
+        }
+        let path = m.as_str().trim_end_matches(TRAILING_PUNCTUATION);
+        if !looks_like_path(path) {
+            continue;
+        }
+        let target = resolve_link_target(path, bases)
+            .and_then(|resolved| resolved.canonicalize().ok())
+            .and_then(|resolved| Url::from_file_path(resolved).ok());
+        if let Some(target) = target {
+            links.push((m.start(), m.start() + path.len(), target));
+        }
+    }
+    links.sort_by_key(|(start, _, _)| *start);
+    links
+}
+
+/// Links for `http(s)://` URLs and for paths that exist on disk, resolved
+/// against each of `bases` in turn.
+pub fn find_links(document: &Document, bases: &[PathBuf], encoding: PositionEncoding) -> Vec<DocumentLink> {
+    let mut links = Vec::new();
+    for n in 0..document.line_count() {
+        let line = document.line(n).unwrap_or_default();
+        if line.len() > MAX_LINE_LENGTH {
+            continue;
+        }
+        for (start, end, target) in line_links(line, bases) {
+            links.push(DocumentLink {
+                range: Range {
+                    start: Position::new(n as u32, encoding.column(line, start)),
+                    end: Position::new(n as u32, encoding.column(line, end)),
+                },
+                target: Some(target),
+                tooltip: None,
+                data: None,
+            });
+        }
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link_texts(line: &str) -> Vec<&str> {
+        line_links(line, &[PathBuf::from(".")])
+            .into_iter()
+            .map(|(start, end, _)| &line[start..end])
+            .collect()
+    }
+
+    #[test]
+    fn test_url_links() {
+        let line = "see https://example.com/a/b?c=1. or (http://example.org)";
+        assert_eq!(vec!["https://example.com/a/b?c=1", "http://example.org"], link_texts(line));
+    }
+
+    #[test]
+    fn test_path_links() {
+        let line = "error in src/main.rs:12: see README.md, not missing/file.rs or 1.5";
+        assert_eq!(vec!["src/main.rs", "README.md"], link_texts(line));
+        assert!(link_texts("cd .. && ls ./").is_empty());
+    }
+
+    #[test]
+    fn test_find_links() {
+        let document = Document::new("# docs\r\n😀 ./Cargo.toml".to_string());
+        let links = find_links(&document, &[PathBuf::from(".")], PositionEncoding::Utf16);
+        assert_eq!(1, links.len());
+        assert_eq!(Range::new(Position::new(1, 3), Position::new(1, 15)), links[0].range);
+        assert!(links[0].target.as_ref().unwrap().path().ends_with("/Cargo.toml"));
+    }
+}
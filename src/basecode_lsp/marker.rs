@@ -0,0 +1,120 @@
+use super::lexer::*;
+use super::util::valid_token_char;
+use serde::Deserialize;
+use serde::Serialize;
+use tower_lsp::lsp_types::*;
+
+pub const LIST_MARKERS_METHOD: &str = "basecode/listMarkers";
+pub const DEFAULT_MARKERS: [&str; 4] = ["TODO", "FIXME", "HACK", "XXX"];
+const COMMENT_CLOSERS: [&str; 4] = ["*/", "-->", "-}", "]]"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub start: usize,
+    pub end: usize,
+    pub word: String,
+    pub text: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ListMarkersParams {
+    /// Only list these marker words instead of the configured ones.
+    pub markers: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MarkerLocation {
+    pub uri: Url,
+    pub range: Range,
+    pub marker: String,
+    pub text: String,
+}
+
+pub fn parse_severity(severity: &str) -> Option<DiagnosticSeverity> {
+    match severity {
+        "hint" => Some(DiagnosticSeverity::HINT),
+        "info" | "information" => Some(DiagnosticSeverity::INFORMATION),
+        "warning" => Some(DiagnosticSeverity::WARNING),
+        "error" => Some(DiagnosticSeverity::ERROR),
+        _ => None,
+    }
+}
+
+fn is_word_at(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[end..].chars().next();
+    !before.is_some_and(valid_token_char) && !after.is_some_and(valid_token_char)
+}
+
+fn marker_text(rest: &str) -> &str {
+    let line_end = rest.find(['\n', '\r']).unwrap_or(rest.len());
+    let mut text = rest[..line_end].trim_end();
+    for closer in COMMENT_CLOSERS {
+        text = text.strip_suffix(closer).unwrap_or(text).trim_end();
+    }
+    text
+}
+
+/// Finds marker words in `text`, returning byte offsets. When the syntax of
+/// the file is known only markers inside comments count.
+pub fn find_markers(text: &str, markers: &[String], syntax: Option<&Syntax>) -> Vec<Marker> {
+    let spans = syntax.map(|s| non_code_spans(text, s));
+    let mut found = Vec::new();
+    for word in markers.iter().filter(|word| !word.is_empty()) {
+        for (start, _) in text.match_indices(word.as_str()) {
+            let end = start + word.len();
+            if !is_word_at(text, start, end) {
+                continue;
+            }
+            if let Some(spans) = &spans {
+                if !span_at(spans, start).is_some_and(|span| span.kind == SpanKind::Comment) {
+                    continue;
+                }
+            }
+            let text = marker_text(&text[start..]);
+            found.push(Marker {
+                start,
+                end: start + text.len(),
+                word: word.clone(),
+                text: text.to_string(),
+            });
+        }
+    }
+    found.sort_by_key(|marker| marker.start);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markers() -> Vec<String> {
+        DEFAULT_MARKERS.iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn test_find_markers() {
+        let text = "TODO: first\nnot a TODOS or XXXL\r\nFIXME(me) second -->\nHACK";
+        let found = find_markers(text, &markers(), None);
+        let texts: Vec<&str> = found.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(vec!["TODO: first", "FIXME(me) second", "HACK"], texts);
+        assert_eq!("FIXME", found[1].word);
+        assert_eq!((0, 11), (found[0].start, found[0].end));
+    }
+
+    #[test]
+    fn test_find_markers_in_comments() {
+        let text = "let s = \"TODO not a marker\"; // TODO: real one\n/* XXX block */";
+        let found = find_markers(text, &markers(), get_syntax("main.rs"));
+        let texts: Vec<&str> = found.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(vec!["TODO: real one", "XXX block"], texts);
+    }
+
+    #[test]
+    fn test_parse_severity() {
+        assert_eq!(Some(DiagnosticSeverity::HINT), parse_severity("hint"));
+        assert_eq!(Some(DiagnosticSeverity::INFORMATION), parse_severity("info"));
+        assert_eq!(None, parse_severity("loud"));
+    }
+}
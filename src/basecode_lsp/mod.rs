@@ -1,7 +1,26 @@
 pub mod backend;
 pub mod command;
+pub mod config;
+pub mod daemon;
+pub mod document;
+pub mod encoding;
+pub mod expansion;
 pub mod file;
+pub mod folding;
+pub mod inline;
+pub mod lexer;
+pub mod link;
+pub mod marker;
+pub mod progress;
+pub mod rename;
 pub mod snippet;
+pub mod spell;
+pub mod symbol;
+pub mod tags;
 pub mod tmux;
+pub mod transport;
 pub mod trie;
+pub mod ultisnips;
 pub mod util;
+pub mod vscode;
+pub mod workspace;
@@ -0,0 +1,130 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tower_lsp::lsp_types::notification;
+use tower_lsp::lsp_types::request;
+use tower_lsp::lsp_types::*;
+use tower_lsp::Client;
+
+// tower-lsp does not route this notification to the LanguageServer trait yet
+pub const WORK_DONE_PROGRESS_CANCEL_METHOD: &str = "window/workDoneProgress/cancel";
+
+static NEXT_TOKEN: AtomicU32 = AtomicU32::new(1);
+
+pub fn token_key(token: &ProgressToken) -> String {
+    match token {
+        NumberOrString::Number(n) => n.to_string(),
+        NumberOrString::String(s) => s.clone(),
+    }
+}
+
+fn percentage(done: usize, total: usize) -> u32 {
+    if total == 0 {
+        return 100;
+    }
+    (done.min(total) * 100 / total) as u32
+}
+
+/// Reports a long running operation through `$/progress`. When the client
+/// does not support work done progress nothing is sent, but cancellation
+/// still works through the shared flag.
+#[derive(Debug)]
+pub struct ProgressReporter {
+    client: Option<Client>,
+    token: ProgressToken,
+    cancelled: Arc<AtomicBool>,
+    percentage: Option<u32>,
+}
+
+impl ProgressReporter {
+    pub async fn begin(client: &Client, title: &str, supported: bool) -> Self {
+        let token = NumberOrString::String(format!("basecode-progress-{}", NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)));
+        let mut reporter = ProgressReporter {
+            client: None,
+            token: token.clone(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            percentage: None,
+        };
+        if !supported {
+            return reporter;
+        }
+        let created = client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams { token })
+            .await;
+        if created.is_err() {
+            return reporter;
+        }
+        reporter.client = Some(client.clone());
+        reporter
+            .send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.to_string(),
+                cancellable: Some(true),
+                message: None,
+                percentage: Some(0),
+            }))
+            .await;
+        reporter
+    }
+
+    async fn send(&self, progress: WorkDoneProgress) {
+        if let Some(client) = &self.client {
+            let params = ProgressParams {
+                token: self.token.clone(),
+                value: ProgressParamsValue::WorkDone(progress),
+            };
+            client.send_notification::<notification::Progress>(params).await;
+        }
+    }
+
+    pub fn token(&self) -> &ProgressToken {
+        &self.token
+    }
+
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Reports `done` out of `total` steps. Nothing is sent unless the
+    /// percentage changed, so this can be called for every file.
+    pub async fn report(&mut self, done: usize, total: usize, message: String) {
+        let percentage = percentage(done, total);
+        if self.percentage == Some(percentage) {
+            return;
+        }
+        self.percentage = Some(percentage);
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(true),
+            message: Some(message),
+            percentage: Some(percentage),
+        }))
+        .await;
+    }
+
+    pub async fn end(self, message: String) {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd { message: Some(message) })).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_key() {
+        assert_eq!("7", token_key(&NumberOrString::Number(7)));
+        assert_eq!("basecode-progress-1", token_key(&NumberOrString::String("basecode-progress-1".to_string())));
+    }
+
+    #[test]
+    fn test_percentage() {
+        assert_eq!(0, percentage(0, 3));
+        assert_eq!(33, percentage(1, 3));
+        assert_eq!(100, percentage(5, 3));
+        assert_eq!(100, percentage(0, 0));
+    }
+}
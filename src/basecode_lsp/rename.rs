@@ -0,0 +1,133 @@
+use super::document::Document;
+use super::encoding::PositionEncoding;
+use super::lexer::*;
+use super::util::*;
+use tower_lsp::lsp_types::*;
+
+pub fn is_identifier(word: &str) -> bool {
+    match word.chars().next() {
+        Some(first) => !first.is_ascii_digit() && word.chars().all(valid_token_char),
+        None => false,
+    }
+}
+
+pub fn find_word_occurrences(
+    document: &Document,
+    word: &str,
+    syntax: Option<&Syntax>,
+    encoding: PositionEncoding,
+) -> Vec<Range> {
+    let spans = syntax.map_or(Vec::new(), |s| non_code_spans(document.text(), s));
+    let mut ranges = Vec::new();
+    for line_number in 0..document.line_count() {
+        let (offset, end) = document.line_range(line_number).unwrap_or_default();
+        let line = &document.text()[offset..end];
+        let mut start: Option<usize> = None;
+        for (byte, ch) in line.char_indices().chain([(line.len(), ' ')]) {
+            if valid_token_char(ch) {
+                start.get_or_insert(byte);
+                continue;
+            }
+            if let Some(start_byte) = start.take() {
+                if &line[start_byte..byte] == word && span_at(&spans, offset + start_byte).is_none() {
+                    ranges.push(Range {
+                        start: Position::new(line_number as u32, encoding.column(line, start_byte)),
+                        end: Position::new(line_number as u32, encoding.column(line, byte)),
+                    });
+                }
+            }
+        }
+    }
+    ranges
+}
+
+pub fn rename_edits(
+    document: &Document,
+    word: &str,
+    new_name: &str,
+    syntax: Option<&Syntax>,
+    encoding: PositionEncoding,
+) -> Vec<TextEdit> {
+    find_word_occurrences(document, word, syntax, encoding)
+        .into_iter()
+        .map(|range| TextEdit {
+            range,
+            new_text: new_name.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(text: &str) -> Document {
+        Document::new(text.to_string())
+    }
+
+    fn range(line: u32, start: u32, end: u32) -> Range {
+        Range {
+            start: Position::new(line, start),
+            end: Position::new(line, end),
+        }
+    }
+
+    #[test]
+    fn test_is_identifier() {
+        assert!(is_identifier("count"));
+        assert!(is_identifier("_count2"));
+        assert!(!is_identifier("2count"));
+        assert!(!is_identifier("count-2"));
+        assert!(!is_identifier(""));
+    }
+
+    #[test]
+    fn test_find_word_occurrences() {
+        let text = "count = 1\ncount += counter\nprint(count)";
+        let ranges = find_word_occurrences(&document(text), "count", None, PositionEncoding::Utf16);
+        assert_eq!(vec![range(0, 0, 5), range(1, 0, 5), range(2, 6, 11)], ranges);
+
+        let text = "count = 1\r\ncount += 1\rprint(count)";
+        let ranges = find_word_occurrences(&document(text), "count", None, PositionEncoding::Utf16);
+        assert_eq!(vec![range(0, 0, 5), range(1, 0, 5), range(2, 6, 11)], ranges);
+    }
+
+    #[test]
+    fn test_find_word_occurrences_skips_comments_and_strings() {
+        let text = "count = 1 # count\nprint(\"count\", count)";
+        let syntax = get_syntax("test.py");
+        let ranges = find_word_occurrences(&document(text), "count", syntax, PositionEncoding::Utf16);
+        assert_eq!(vec![range(0, 0, 5), range(1, 15, 20)], ranges);
+
+        let ranges = find_word_occurrences(&document(text), "count", None, PositionEncoding::Utf16);
+        assert_eq!(4, ranges.len());
+    }
+
+    #[test]
+    fn test_find_word_occurrences_unicode() {
+        let text = "héllo = héllo_2 + héllo";
+        let ranges = find_word_occurrences(&document(text), "héllo", None, PositionEncoding::Utf16);
+        assert_eq!(vec![range(0, 0, 5), range(0, 18, 23)], ranges);
+
+        let text = "😀 héllo";
+        let ranges = find_word_occurrences(&document(text), "héllo", None, PositionEncoding::Utf16);
+        assert_eq!(vec![range(0, 3, 8)], ranges);
+        let ranges = find_word_occurrences(&document(text), "héllo", None, PositionEncoding::Utf8);
+        assert_eq!(vec![range(0, 5, 11)], ranges);
+    }
+
+    #[test]
+    fn test_rename_edits_after_rust_char_literal() {
+        let text = "let quote = '\"'; let count = quote.len() + count;\nlet raw = r#\"count\"#; count";
+        let edits = rename_edits(&document(text), "count", "total", get_syntax("main.rs"), PositionEncoding::Utf16);
+        let ranges: Vec<Range> = edits.iter().map(|edit| edit.range).collect();
+        assert_eq!(vec![range(0, 21, 26), range(0, 43, 48), range(1, 22, 27)], ranges);
+    }
+
+    #[test]
+    fn test_rename_edits() {
+        let edits = rename_edits(&document("a = b\r\nb = a"), "a", "value", None, PositionEncoding::Utf16);
+        assert_eq!(2, edits.len());
+        assert!(edits.iter().all(|edit| edit.new_text == "value"));
+    }
+}
@@ -1,18 +1,68 @@
+use super::config::Settings;
+use super::ultisnips::is_ultisnips;
+use super::ultisnips::parse_ultisnips;
+use super::vscode::is_vscode_snippets;
+use super::vscode::parse_vscode_snippets;
 use glob::glob;
 use hashbrown::HashMap;
 use simple_log::error;
+use simple_log::info;
+use simple_log::warn;
+use std::cmp::Reverse;
+use std::collections::VecDeque;
 use std::fs;
 use std::path::Path;
-use std::sync::OnceLock;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use tower_lsp::lsp_types::MessageType;
+use tower_lsp::Client;
+
+pub const SNIPPET_EXTENSION: &str = "snippets";
+pub const SNIPPET_EXTENSIONS: [&str; 3] = [SNIPPET_EXTENSION, "json", "code-snippets"];
+/// Snippets of this filetype are offered in every buffer.
+pub const GLOBAL_FILETYPE: &str = "_";
+/// The name UltiSnips gives the global filetype.
+const ALL_FILETYPE: &str = "all";
+const SNIPPET_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The syntax a snippet body is written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnippetSyntax {
+    /// SnipMate and UltiSnips bodies, translated for the client.
+    SnipMate,
+    /// VSCode bodies, which the client takes as they are.
+    Lsp,
+}
 
 #[derive(Debug, Clone)]
 pub struct Snippet {
+    /// The trigger completion matches against.
     pub name: String,
     pub snippet: String,
     pub filetype: String,
+    pub description: Option<String>,
+    /// Trailing header options, e.g. `b` or `w`.
+    pub options: String,
+    /// UltiSnips priority; snippets with a higher one are offered first.
+    pub priority: i32,
+    pub syntax: SnippetSyntax,
 }
 
 impl Snippet {
+    /// Whether the snippet is offered for `prefix`. `before` is the text of
+    /// the line in front of the prefix; with the `b` option the trigger only
+    /// expands at the start of a line. The `w` option always holds, since the
+    /// prefix is a whole word.
+    pub fn matches(&self, prefix: &str, before: &str) -> bool {
+        if self.options.contains('b') && !before.trim().is_empty() {
+            return false;
+        }
+        self.name.contains(prefix)
+    }
+
     pub fn markdown(&self) -> String {
         format!(
             "```{format}\n{snippet}```",
@@ -22,38 +72,35 @@ impl Snippet {
     }
 }
 
-pub fn snippet_patterns() -> &'static HashMap<&'static str, Vec<&'static str>> {
-    static SNIPPETS: OnceLock<HashMap<&str, Vec<&str>>> = OnceLock::new();
-    SNIPPETS.get_or_init(|| {
-        let mut m = HashMap::new();
-        m.insert("c", vec![".c", ".h", ".cc", ".cpp"]);
-        m.insert("cpp", vec![".cpp", ".cc", ".h", ".hpp"]);
-        m.insert("cmake", vec![".cmake", "CMakeLists.txt"]);
-        m.insert("dart", vec![".dart"]);
-        m.insert("javascript", vec![".js", ".ts"]);
-        m.insert("json", vec![".json"]);
-        m.insert("kotlin", vec![".kt"]);
-        m.insert("python", vec![".py", ".pyc"]);
-        m.insert("rust", vec![".rs", ".rst"]);
-        m.insert("sh", vec![".sh", ".zsh", ".bash"]);
-        m.insert("zsh", vec![".sh", ".zsh"]);
-        m.insert("clangformat", vec![".clang-format"]);
-        m.insert("yapf", vec![".style.yapf"]);
-        m
-    })
-}
-
-pub fn get_snippet_names(file_uri: &str) -> Vec<&str> {
-    let mut names = Vec::new();
-    for (name, patterns) in snippet_patterns().iter() {
-        for p in patterns.iter() {
-            if file_uri.ends_with(*p) {
-                names.push(*name);
-                break;
-            }
+/// The file name endings of each snippet filetype, each list starting with
+/// the ending the filetype is most specific to.
+pub const SNIPPET_PATTERNS: [(&str, &[&str]); 13] = [
+    ("cpp", &[".cpp", ".cc", ".h", ".hpp"]),
+    ("c", &[".c", ".h", ".cc", ".cpp"]),
+    ("cmake", &[".cmake", "CMakeLists.txt"]),
+    ("dart", &[".dart"]),
+    ("javascript", &[".js", ".ts"]),
+    ("json", &[".json"]),
+    ("kotlin", &[".kt"]),
+    ("python", &[".py", ".pyc"]),
+    ("rust", &[".rs", ".rst"]),
+    ("sh", &[".sh", ".bash", ".zsh"]),
+    ("zsh", &[".zsh", ".sh"]),
+    ("clangformat", &[".clang-format"]),
+    ("yapf", &[".style.yapf"]),
+];
+
+/// The snippet filetypes of a file, most specific first: those whose list
+/// has the matching ending nearer the start, then in declaration order.
+pub fn get_snippet_names(file_uri: &str) -> Vec<&'static str> {
+    let mut names: Vec<(usize, &str)> = Vec::new();
+    for (name, patterns) in SNIPPET_PATTERNS.iter() {
+        if let Some(position) = patterns.iter().position(|p| file_uri.ends_with(*p)) {
+            names.push((position, *name));
         }
     }
-    names
+    names.sort_by_key(|(position, _)| *position);
+    names.into_iter().map(|(_, name)| name).collect()
 }
 
 fn transform_line(input: &str) -> String {
@@ -73,39 +120,256 @@ fn transform_line(input: &str) -> String {
     result
 }
 
-fn parse_snippets(content: &str, filetype: &str) -> Vec<Snippet> {
-    let mut snippets = Vec::new();
+/// Splits a header such as `for "for loop" b` into the trigger, the
+/// description and the options. A trigger containing spaces is quoted, and a
+/// SnipMate description may be left unquoted.
+pub fn parse_header(header: &str) -> (String, Option<String>, String) {
+    let header = header.trim();
+    let (trigger, rest) = match header.strip_prefix('"').and_then(|quoted| quoted.split_once('"')) {
+        Some((trigger, rest)) => (trigger, rest.trim()),
+        None => header.split_once(char::is_whitespace).map_or((header, ""), |(trigger, rest)| (trigger, rest.trim())),
+    };
+    let (description, options) = match rest.strip_prefix('"').and_then(|quoted| quoted.rsplit_once('"')) {
+        Some((description, options)) if !options.trim().contains(char::is_whitespace) => (description, options.trim()),
+        _ => (rest, ""),
+    };
+    let description = (!description.is_empty()).then(|| description.to_string());
+    (trigger.to_string(), description, options.to_string())
+}
+
+/// Parses the filetypes of an `extends` line such as `extends c, cpp`.
+pub fn parse_extends(line: &str) -> Option<Vec<String>> {
+    let parents = line.strip_prefix("extends")?;
+    if !parents.is_empty() && !parents.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(parents.split(',').map(str::trim).filter(|parent| !parent.is_empty()).map(str::to_string).collect())
+}
+
+/// Parses a SnipMate snippet file, returning the snippets, the filetypes it
+/// extends and a message for each line that could not be parsed.
+fn parse_snippets(content: &str, filetype: &str) -> SnippetFile {
+    let mut file = SnippetFile {
+        filetype: Some(filetype.to_string()),
+        ..SnippetFile::default()
+    };
     let lines: Vec<&str> = content.split("\n").collect();
     let mut i = 0;
     while i < lines.len() {
         let line = lines[i];
-        if line.starts_with("snippet") {
-            let snippet_name = &line[("snippet".len() + 1)..];
+        if let Some(parents) = parse_extends(line.trim_end()) {
+            file.extends.extend(parents);
+            i += 1;
+        } else if let Some(rest) = line.strip_prefix("snippet") {
+            let (snippet_name, description, options) = parse_header(rest);
+            if snippet_name.is_empty() {
+                file.errors.push(format!("line {}: snippet without a trigger", i + 1));
+            }
             i += 1;
             let mut content_lines = Vec::new();
-            while i < lines.len() && !lines[i].starts_with("snippet") {
+            while i < lines.len() && !lines[i].starts_with("snippet") && parse_extends(lines[i].trim_end()).is_none() {
                 if !lines[i].starts_with("#") && lines[i].starts_with("\t") {
                     content_lines.push(transform_line(lines[i]));
                 }
                 i += 1;
             }
-            snippets.push(Snippet {
-                name: snippet_name.to_string(),
-                snippet: content_lines.join("\n").to_string(),
-                filetype: filetype.to_string(),
-            });
+            if !snippet_name.is_empty() {
+                file.snippets.push(Snippet {
+                    name: snippet_name,
+                    snippet: content_lines.join("\n").to_string(),
+                    filetype: filetype.to_string(),
+                    description,
+                    options,
+                    priority: 0,
+                    syntax: SnippetSyntax::SnipMate,
+                });
+            }
         } else {
             i += 1;
         }
     }
-    snippets
+    file
+}
+
+pub fn is_snippet_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| SNIPPET_EXTENSIONS.iter().any(|e| extension == *e))
+}
+
+/// The filetype a SnipMate or VSCode language snippet file provides snippets
+/// for, with `all` meaning every filetype as in UltiSnips. `.code-snippets`
+/// files name it in the scope of each snippet instead.
+pub fn snippet_filetype(path: &Path) -> Option<String> {
+    let extension = path.extension()?;
+    if extension != SNIPPET_EXTENSION && extension != "json" {
+        return None;
+    }
+    match get_file_basename(path.display().to_string()) {
+        basename if basename == ALL_FILETYPE => Some(GLOBAL_FILETYPE.to_string()),
+        basename => Some(basename),
+    }
+}
+
+/// The contents of one snippet file.
+#[derive(Debug, Clone, Default)]
+pub struct SnippetFile {
+    /// `None` for `.code-snippets` files, whose snippets name their filetype.
+    pub filetype: Option<String>,
+    pub snippets: Vec<Snippet>,
+    /// Filetypes whose snippets are offered along with this one's.
+    pub extends: Vec<String>,
+    pub errors: Vec<String>,
+    /// Parts of the file that were skipped because they are not supported.
+    pub warnings: Vec<String>,
+}
+
+/// Loaded snippets grouped by filetype, and the filetypes each one extends.
+#[derive(Debug, Default, Clone)]
+pub struct SnippetStore {
+    pub snippets: HashMap<String, Vec<Snippet>>,
+    pub extends: HashMap<String, Vec<String>>,
+}
+
+impl SnippetStore {
+    pub fn add(&mut self, file: SnippetFile) {
+        if let Some(filetype) = file.filetype.filter(|_| !file.extends.is_empty()) {
+            let extends = self.extends.entry(filetype).or_default();
+            for parent in file.extends {
+                if !extends.contains(&parent) {
+                    extends.push(parent);
+                }
+            }
+        }
+        for snippet in file.snippets {
+            self.snippets.entry(snippet.filetype.clone()).or_default().push(snippet);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snippets.is_empty()
+    }
+
+    pub fn snippet_count(&self) -> usize {
+        self.snippets.values().map(Vec::len).sum()
+    }
+
+    fn follow_extends(&self, start: Vec<String>, result: &mut Vec<String>) {
+        let mut queue = VecDeque::from(start);
+        while let Some(filetype) = queue.pop_front() {
+            // a filetype seen before ends an extends cycle
+            if result.contains(&filetype) {
+                continue;
+            }
+            queue.extend(self.extends.get(&filetype).into_iter().flatten().cloned());
+            result.push(filetype);
+        }
+    }
+
+    /// The filetypes whose snippets a buffer of `filetypes` is offered, nearest
+    /// first: those, the ones they extend transitively, then the global ones.
+    pub fn filetypes(&self, filetypes: &[&str]) -> Vec<String> {
+        let mut result = Vec::new();
+        self.follow_extends(filetypes.iter().map(|filetype| filetype.to_string()).collect(), &mut result);
+        self.follow_extends(vec![GLOBAL_FILETYPE.to_string()], &mut result);
+        result
+    }
+
+    /// The snippets a buffer of `filetypes` is offered for `prefix`. Of the
+    /// snippets sharing a trigger only those with the highest priority are
+    /// kept, and on a tie those of the nearest filetype.
+    pub fn suggest(&self, filetypes: &[&str], prefix: &str, before: &str) -> Vec<Snippet> {
+        let mut candidates: Vec<(usize, &Snippet)> = Vec::new();
+        for (rank, filetype) in self.filetypes(filetypes).iter().enumerate() {
+            for snippet in self.snippets.get(filetype).into_iter().flatten() {
+                if snippet.matches(prefix, before) {
+                    candidates.push((rank, snippet));
+                }
+            }
+        }
+        let mut best: HashMap<&str, (i32, Reverse<usize>)> = HashMap::new();
+        for (rank, snippet) in candidates.iter() {
+            let key = (snippet.priority, Reverse(*rank));
+            let entry = best.entry(snippet.name.as_str()).or_insert(key);
+            *entry = (*entry).max(key);
+        }
+        let mut result: Vec<Snippet> = candidates
+            .into_iter()
+            .filter(|(rank, snippet)| best[snippet.name.as_str()] == (snippet.priority, Reverse(*rank)))
+            .map(|(_, snippet)| snippet.clone())
+            .collect();
+        result.sort_by_key(|snippet| Reverse(snippet.priority));
+        result
+    }
+}
+
+pub type SharedSnippetStore = Arc<Mutex<SnippetStore>>;
+
+/// Snippet stores keyed by the folders they are loaded from, so that daemon
+/// sessions only share snippets when they use the same folders. A store no
+/// session holds any more is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct SnippetStores {
+    stores: Arc<Mutex<HashMap<Vec<String>, SharedSnippetStore>>>,
+}
+
+impl SnippetStores {
+    pub async fn get(&self, folders: &[String]) -> SharedSnippetStore {
+        let mut stores = self.stores.lock().await;
+        stores.retain(|key, store| key == folders || Arc::strong_count(store) > 1);
+        stores.entry(folders.to_vec()).or_default().clone()
+    }
+}
+
+/// Reads and parses one snippet file. Errors are prefixed with the path and
+/// warnings are logged. `.snippets` files are read as UltiSnips or SnipMate
+/// depending on their content.
+pub fn load_snippet_file(path: &Path) -> SnippetFile {
+    let filetype = snippet_filetype(path);
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            return SnippetFile {
+                filetype,
+                errors: vec![format!("{}: {}", path.display(), e)],
+                ..SnippetFile::default()
+            }
+        }
+    };
+    let mut file = if path.extension().is_some_and(|extension| extension == SNIPPET_EXTENSION) {
+        let filetype = filetype.unwrap_or_default();
+        if is_ultisnips(&content) {
+            parse_ultisnips(&content, &filetype)
+        } else {
+            parse_snippets(&content, &filetype)
+        }
+    } else if path.extension().is_some_and(|extension| extension == "json") && !is_vscode_snippets(&content) {
+        info!("{}: not a snippet file, skipped", path.display());
+        return SnippetFile::default();
+    } else {
+        let (snippets, errors) = parse_vscode_snippets(&content, filetype.as_deref());
+        SnippetFile {
+            filetype,
+            snippets,
+            errors,
+            ..SnippetFile::default()
+        }
+    };
+    for warning in file.warnings.iter() {
+        warn!("{}: {}", path.display(), warning);
+    }
+    file.errors = file.errors.iter().map(|e| format!("{}: {}", path.display(), e)).collect();
+    file
 }
 
-fn read_snippet(path: &Path, filetype: &str) -> Vec<Snippet> {
-    if let Ok(content) = fs::read_to_string(path) {
-        return parse_snippets(&content, filetype);
+/// Loads every snippet file of `folders`.
+pub fn load_snippet_folders(folders: &[String]) -> (SnippetStore, Vec<String>) {
+    let mut store = SnippetStore::default();
+    let mut errors = Vec::new();
+    for path in folders.iter().flat_map(|folder| list_snippet_files(folder)) {
+        let mut file = load_snippet_file(&path);
+        errors.append(&mut file.errors);
+        store.add(file);
     }
-    Vec::new()
+    (store, errors)
 }
 
 fn get_file_basename(path: String) -> String {
@@ -119,23 +383,96 @@ fn get_file_basename(path: String) -> String {
     }
 }
 
-pub fn prepare_snippet(snippet_path: String, snippets: &mut HashMap<String, Vec<Snippet>>) {
-    let target = format!("{}/*.snippets", snippet_path);
-    if let Ok(paths) = glob(&target) {
-        for entry in paths {
-            match entry {
-                Ok(path) => {
-                    let p = path.as_path();
-                    let filename = get_file_basename(p.display().to_string());
-                    let all_snippets = read_snippet(&p, &filename);
-                    snippets.insert(filename, all_snippets);
-                }
-                Err(e) => {
-                    error!("{:?}", e);
+pub fn list_snippet_files(snippet_path: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for extension in SNIPPET_EXTENSIONS {
+        let target = format!("{}/*.{}", snippet_path, extension);
+        if let Ok(paths) = glob(&target) {
+            for entry in paths {
+                match entry {
+                    Ok(path) => files.push(path),
+                    Err(e) => {
+                        error!("{:?}", e);
+                    }
                 }
             }
         }
     }
+    files
+}
+
+fn snippet_file_times(folders: &[String]) -> Vec<(PathBuf, SystemTime)> {
+    folders
+        .iter()
+        .flat_map(|folder| list_snippet_files(folder))
+        .filter_map(|path| {
+            let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .collect()
+}
+
+/// Parsed snippet files by path, with the modification time they were read at.
+type SnippetFileCache = HashMap<PathBuf, (SystemTime, SnippetFile)>;
+
+/// Parses the files of `folders` that are new or changed since they went into
+/// `cache` and forgets the removed ones. When any file changed, returns the
+/// snippets of all files and the errors of the parsed ones.
+fn refresh_snippet_files(folders: &[String], cache: &mut SnippetFileCache) -> Option<(SnippetStore, Vec<String>)> {
+    let files = snippet_file_times(folders);
+    let count = cache.len();
+    cache.retain(|path, _| files.iter().any(|(file, _)| file == path));
+    let mut changed = cache.len() != count;
+    let mut errors = Vec::new();
+    for (path, modified) in files.iter() {
+        if cache.get(path).is_some_and(|(known, _)| known == modified) {
+            continue;
+        }
+        let mut file = load_snippet_file(path);
+        errors.append(&mut file.errors);
+        cache.insert(path.clone(), (*modified, file));
+        changed = true;
+    }
+    if !changed {
+        return None;
+    }
+    let mut store = SnippetStore::default();
+    for (path, _) in files.iter() {
+        if let Some((_, file)) = cache.get(path) {
+            store.add(file.clone());
+        }
+    }
+    Some((store, errors))
+}
+
+/// Fallback for clients that cannot watch files for us: polls the snippet
+/// folders and re-parses the files that changed.
+pub async fn poll_snippet_folder(client: Client, settings: Arc<Mutex<Settings>>, stores: SnippetStores) {
+    let mut folders: Vec<String> = Vec::new();
+    let mut cache = SnippetFileCache::new();
+    loop {
+        let current = settings.lock().await.all_snippet_folders();
+        // the backend reloads the folders itself when the setting changes
+        let reset = current != folders;
+        if reset {
+            folders = current;
+            cache.clear();
+        }
+        let scanned = folders.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let changes = refresh_snippet_files(&scanned, &mut cache);
+            (cache, changes)
+        });
+        let (scanned_cache, changes) = task.await.unwrap_or_default();
+        cache = scanned_cache;
+        if let (false, Some((loaded, errors))) = (reset, changes) {
+            *stores.get(&folders).await.lock().await = loaded;
+            if !errors.is_empty() {
+                client.show_message(MessageType::ERROR, errors.join("\n")).await;
+            }
+        }
+        tokio::time::sleep(SNIPPET_POLL_INTERVAL).await;
+    }
 }
 
 #[cfg(test)]
@@ -164,7 +501,7 @@ mod test {
 \t\t// code here...
 \t\treturn 0;
 \t}";
-        let snippets = parse_snippets(content, "cpp");
+        let snippets = parse_snippets(content, "cpp").snippets;
         assert_eq!(1, snippets.len());
 
         let snippet = &snippets[0];
@@ -181,7 +518,7 @@ mod test {
 \tfor (size_t i = 0; i < count; i++) {
 \t\t/* code */
 \t}";
-        let snippets = parse_snippets(content, "cpp");
+        let snippets = parse_snippets(content, "cpp").snippets;
         assert_eq!(1, snippets.len());
 
         let snippet = &snippets[0];
@@ -197,7 +534,7 @@ mod test {
 \tif (condition) {
 \t\t/* code */
 \t}";
-        let snippets = parse_snippets(content, "cpp");
+        let snippets = parse_snippets(content, "cpp").snippets;
         assert_eq!(1, snippets.len());
 
         let snippet = &snippets[0];
@@ -210,6 +547,188 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(("for".to_string(), Some("for loop".to_string()), String::new()), parse_header(" for \"for loop\""));
+        assert_eq!(("if".to_string(), Some("if \"x\"".to_string()), "b".to_string()), parse_header(" if \"if \"x\"\" b"));
+        assert_eq!(("two words".to_string(), None, String::new()), parse_header(" \"two words\""));
+        assert_eq!(("inc".to_string(), Some("#include <...>".to_string()), String::new()), parse_header(" inc #include <...>"));
+        assert_eq!(("main".to_string(), None, String::new()), parse_header(" main"));
+
+        let snippets = parse_snippets("snippet for \"for loop\" b\n\tfor {}", "go").snippets;
+        assert_eq!("for", snippets[0].name);
+        assert_eq!(Some("for loop".to_string()), snippets[0].description);
+        assert_eq!("b", snippets[0].options);
+        assert!(snippets[0].matches("fo", "  "));
+        assert!(!snippets[0].matches("fo", "x := "));
+    }
+
+    #[test]
+    fn test_parse_snippets_errors() {
+        let content = "snippet\n\tnothing\nsnippet ok\n\tfine";
+        let file = parse_snippets(content, "c");
+        assert_eq!(1, file.snippets.len());
+        assert_eq!("ok", file.snippets[0].name);
+        assert_eq!(vec!["line 1: snippet without a trigger"], file.errors);
+    }
+
+    #[test]
+    fn test_parse_extends() {
+        assert_eq!(Some(vec!["c".to_string(), "objc".to_string()]), parse_extends("extends c, objc"));
+        assert_eq!(None, parse_extends("extended"));
+
+        let file = parse_snippets("extends c\nsnippet cls\n\tclass $1 {};\nextends objc\n", "cpp");
+        assert_eq!(vec!["c", "objc"], file.extends);
+        assert_eq!("class $1 {};", file.snippets[0].snippet);
+    }
+
+    fn snippet(name: &str, filetype: &str, priority: i32) -> Snippet {
+        Snippet {
+            name: name.to_string(),
+            snippet: format!("{} {}", filetype, name),
+            filetype: filetype.to_string(),
+            description: None,
+            options: String::new(),
+            priority,
+            syntax: SnippetSyntax::SnipMate,
+        }
+    }
+
+    #[test]
+    fn test_snippet_store_filetypes() {
+        let mut store = SnippetStore::default();
+        for (filetype, parents) in [("cpp", vec!["c"]), ("c", vec!["cpp", "doxygen"]), (GLOBAL_FILETYPE, vec!["license"])] {
+            store.add(SnippetFile {
+                filetype: Some(filetype.to_string()),
+                extends: parents.into_iter().map(str::to_string).collect(),
+                ..SnippetFile::default()
+            });
+        }
+        // the cpp <-> c cycle ends, and global filetypes come last
+        assert_eq!(vec!["cpp", "c", "doxygen", GLOBAL_FILETYPE, "license"], store.filetypes(&["cpp"]));
+        assert_eq!(vec!["rust", GLOBAL_FILETYPE, "license"], store.filetypes(&["rust"]));
+    }
+
+    #[test]
+    fn test_snippet_store_suggest() {
+        let mut store = SnippetStore::default();
+        store.add(SnippetFile {
+            filetype: Some("cpp".to_string()),
+            snippets: vec![snippet("main", "cpp", 0), snippet("for", "cpp", 0), snippet("for", "cpp", 0)],
+            extends: vec!["c".to_string()],
+            ..SnippetFile::default()
+        });
+        store.add(SnippetFile {
+            filetype: Some("c".to_string()),
+            snippets: vec![snippet("main", "c", 0), snippet("inc", "c", 0), snippet("for", "c", 10)],
+            ..SnippetFile::default()
+        });
+        store.add(SnippetFile {
+            filetype: Some(GLOBAL_FILETYPE.to_string()),
+            snippets: vec![snippet("date", GLOBAL_FILETYPE, 0), snippet("inc", GLOBAL_FILETYPE, -1)],
+            ..SnippetFile::default()
+        });
+        let suggested: Vec<String> = store.suggest(&["cpp"], "", "").iter().map(|snippet| snippet.snippet.clone()).collect();
+        assert_eq!(vec!["c for", "cpp main", "c inc", "_ date"], suggested);
+        let suggested: Vec<String> = store.suggest(&["c"], "ma", "").iter().map(|snippet| snippet.snippet.clone()).collect();
+        assert_eq!(vec!["c main"], suggested);
+
+        // for a C++ source the cpp snippet wins over the c one of the same trigger
+        let filetypes = get_snippet_names("file:///src/main.cpp");
+        let suggested: Vec<String> = store.suggest(&filetypes, "main", "").iter().map(|snippet| snippet.snippet.clone()).collect();
+        assert_eq!(vec!["cpp main"], suggested);
+
+        // equally important snippets of one filetype are all kept
+        store.snippets.get_mut("c").unwrap().retain(|snippet| snippet.name != "for");
+        let suggested = store.suggest(&["cpp"], "fo", "");
+        assert_eq!(2, suggested.len());
+    }
+
+    #[tokio::test]
+    async fn test_snippet_stores() {
+        let stores = SnippetStores::default();
+        let first = stores.get(&["/a".to_string()]).await;
+        let other = stores.get(&["/b".to_string()]).await;
+        first.lock().await.add(SnippetFile {
+            snippets: vec![snippet("main", "c", 0)],
+            ..SnippetFile::default()
+        });
+        assert!(other.lock().await.is_empty());
+        assert!(Arc::ptr_eq(&first, &stores.get(&["/a".to_string()]).await));
+
+        // a store nobody holds is loaded again
+        drop(first);
+        stores.get(&["/b".to_string()]).await;
+        assert!(stores.get(&["/a".to_string()]).await.lock().await.is_empty());
+    }
+
+    #[test]
+    fn test_snippet_filetype() {
+        assert_eq!(Some("rust".to_string()), snippet_filetype(Path::new("/snippets/rust.snippets")));
+        assert_eq!(Some("rust".to_string()), snippet_filetype(Path::new("/snippets/rust.json")));
+        assert_eq!(None, snippet_filetype(Path::new("/snippets/web.code-snippets")));
+        assert_eq!(Some(GLOBAL_FILETYPE.to_string()), snippet_filetype(Path::new("/UltiSnips/all.snippets")));
+        assert!(is_snippet_file(Path::new("/snippets/web.code-snippets")));
+        assert!(!is_snippet_file(Path::new("/snippets/README.md")));
+    }
+
+    #[test]
+    fn test_load_snippet_folders() {
+        let root = std::env::temp_dir().join("basecode-lsp-snippet-folders-test");
+        let folders: Vec<String> = ["first", "second"].iter().map(|name| root.join(name).display().to_string()).collect();
+        for (folder, trigger) in folders.iter().zip(["main", "test"]) {
+            fs::create_dir_all(folder).unwrap();
+            fs::write(Path::new(folder).join("rust.snippets"), format!("snippet {}\n\tbody\n", trigger)).unwrap();
+        }
+        let vscode = r#"{"Print": {"prefix": "pr", "body": "print($1)", "scope": "python"}}"#;
+        fs::write(Path::new(&folders[1]).join("team.code-snippets"), vscode).unwrap();
+        fs::write(Path::new(&folders[1]).join("package.json"), r#"{"name": "snippets", "version": "1.0.0"}"#).unwrap();
+        let ultisnips = "extends c\npriority -50\nsnippet imp \"import\"\n#import \"$1\"\nendsnippet\n";
+        fs::write(Path::new(&folders[0]).join("objc.snippets"), ultisnips).unwrap();
+        let (snippets, errors) = load_snippet_folders(&folders);
+        let mut names: Vec<&str> = snippets.snippets["rust"].iter().map(|snippet| snippet.name.as_str()).collect();
+        names.sort();
+        assert_eq!(vec!["main", "test"], names);
+        assert_eq!("pr", snippets.snippets["python"][0].name);
+        assert_eq!("#import \"$1\"", snippets.snippets["objc"][0].snippet);
+        assert_eq!(-50, snippets.snippets["objc"][0].priority);
+        assert_eq!(vec!["objc", "c", GLOBAL_FILETYPE], snippets.filetypes(&["objc"]));
+        assert!(errors.is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_refresh_snippet_files() {
+        let root = std::env::temp_dir().join("basecode-lsp-snippet-refresh-test");
+        fs::create_dir_all(&root).unwrap();
+        let folders = vec![root.display().to_string()];
+        let write = |name: &str, content: &str, seconds: u64| {
+            let path = root.join(name);
+            fs::write(&path, content).unwrap();
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+            fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        };
+        write("rust.snippets", "snippet fn\n\tfn\n", 1);
+        write("c.snippets", "snippet main\n\tmain\n", 1);
+        let mut cache = SnippetFileCache::new();
+        let (store, errors) = refresh_snippet_files(&folders, &mut cache).unwrap();
+        assert_eq!(2, store.snippet_count());
+        assert!(errors.is_empty());
+        assert!(refresh_snippet_files(&folders, &mut cache).is_none());
+
+        write("rust.snippets", "snippet fn\n\tfn\nsnippet\n\tx\n", 2);
+        let (store, errors) = refresh_snippet_files(&folders, &mut cache).unwrap();
+        assert_eq!(2, store.snippets["rust"].len() + store.snippets["c"].len());
+        assert_eq!(1, errors.len());
+        assert_eq!(2, cache[&root.join("rust.snippets")].0.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs());
+
+        fs::remove_file(root.join("c.snippets")).unwrap();
+        let (store, _) = refresh_snippet_files(&folders, &mut cache).unwrap();
+        assert_eq!(vec!["fn"], store.snippets["rust"].iter().map(|snippet| snippet.name.as_str()).collect::<Vec<&str>>());
+        assert!(!store.snippets.contains_key("c"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
     #[test]
     fn test_transform_line() {
         let transformed = transform_line("\t\treturn 0;");
@@ -218,17 +737,11 @@ mod test {
 
     #[test]
     fn test_get_snippet_names() {
-        let names = get_snippet_names("main.cpp");
-        let expect_included = vec!["cpp", "c"];
-        for t in expect_included.iter() {
-            assert!(names.contains(t));
-        }
-
-        let names = get_snippet_names("main.cc");
-        let expect_included = vec!["cpp", "c"];
-        for t in expect_included.iter() {
-            assert!(names.contains(t));
-        }
+        assert_eq!(vec!["cpp", "c"], get_snippet_names("main.cpp"));
+        assert_eq!(vec!["cpp", "c"], get_snippet_names("main.cc"));
+        assert_eq!(vec!["c", "cpp"], get_snippet_names("main.h"));
+        assert_eq!(vec!["sh", "zsh"], get_snippet_names("run.sh"));
+        assert_eq!(vec!["zsh", "sh"], get_snippet_names("file:///home/user/.zsh"));
 
         let names = get_snippet_names("main.cmake");
         assert!(names.contains(&"cmake"));
@@ -239,7 +752,8 @@ mod test {
 
     #[test]
     fn test_snippet_patterns() {
-        let patterns = snippet_patterns();
+        let patterns: HashMap<&str, &[&str]> = SNIPPET_PATTERNS.into_iter().collect();
+        assert_eq!(SNIPPET_PATTERNS.len(), patterns.len());
         assert!(patterns.contains_key("c"));
         assert!(patterns.contains_key("cpp"));
         assert!(patterns.get("rust").unwrap().contains(&".rs"));
@@ -0,0 +1,370 @@
+use super::lexer::*;
+use super::util::*;
+use hashbrown::HashMap;
+use hashbrown::HashSet;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+pub const DEFAULT_DICTIONARY: &str = "/usr/share/dict/words";
+pub const PROJECT_DICTIONARY: &str = ".basecode-dictionary";
+pub const MIN_VOCABULARY_COUNT: i32 = 3;
+
+const MIN_SPELL_WORD_LEN: usize = 3;
+const MAX_EDIT_DISTANCE: usize = 2;
+const PROSE_PATTERNS: [&str; 7] = [".md", ".markdown", ".txt", ".rst", ".org", ".adoc", "COMMIT_EDITMSG"];
+
+/// Lowercase words grouped by first letter and length, so that suggestions
+/// only compare a word against the few groups it can be close to.
+#[derive(Debug, Default)]
+pub struct Dictionary {
+    words: HashMap<(char, usize), HashSet<String>>,
+    /// Whether the base word list was read; a project dictionary alone is too
+    /// small to check against.
+    loaded: bool,
+}
+
+fn group_of(word: &str) -> Option<(char, usize)> {
+    Some((word.chars().next()?, word.chars().count()))
+}
+
+impl Dictionary {
+    pub fn new() -> Self {
+        Dictionary {
+            words: HashMap::new(),
+            loaded: false,
+        }
+    }
+
+    pub fn load_base(&mut self, path: &Path) -> bool {
+        self.loaded = self.load(path);
+        self.loaded
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    pub fn load(&mut self, path: &Path) -> bool {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                for word in content.lines().map(str::trim).filter(|w| !w.is_empty()) {
+                    self.insert(word);
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn insert(&mut self, word: &str) {
+        let word = word.to_lowercase();
+        if let Some(group) = group_of(&word) {
+            self.words.entry(group).or_default().insert(word);
+        }
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        group_of(&word).and_then(|group| self.words.get(&group)).is_some_and(|words| words.contains(&word))
+    }
+
+    /// The closest words to `word`. Only words with the same first letter are
+    /// considered.
+    pub fn suggest(&self, word: &str, max_suggestions: usize) -> Vec<String> {
+        let target: Vec<char> = word.to_lowercase().chars().collect();
+        let Some(&first) = target.first() else {
+            return Vec::new();
+        };
+        let lengths = target.len().saturating_sub(MAX_EDIT_DISTANCE)..=target.len() + MAX_EDIT_DISTANCE;
+        let mut candidates: Vec<(usize, &String)> = lengths
+            .filter_map(|len| self.words.get(&(first, len)))
+            .flatten()
+            .filter_map(|w| {
+                let distance = edit_distance(&target, &w.chars().collect::<Vec<char>>());
+                (distance <= MAX_EDIT_DISTANCE).then_some((distance, w))
+            })
+            .collect();
+        candidates.sort();
+        candidates
+            .into_iter()
+            .take(max_suggestions)
+            .map(|(_, w)| match_case(word, w))
+            .collect()
+    }
+}
+
+/// How often each lowercase piece of the indexed identifiers occurs, per
+/// document, so that the project's own words count as known.
+#[derive(Debug, Default)]
+pub struct Vocabulary {
+    total: HashMap<String, i32>,
+    by_uri: HashMap<String, HashMap<String, i32>>,
+}
+
+fn vocabulary_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    for token in text.split(|ch: char| !valid_token_char(ch)) {
+        if token.chars().any(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        for (_, piece) in split_identifier(token) {
+            if piece.chars().count() >= MIN_SPELL_WORD_LEN {
+                words.push(piece.to_lowercase());
+            }
+        }
+    }
+    words
+}
+
+impl Vocabulary {
+    pub fn add(&mut self, uri: &str, text: &str) {
+        let counts = self.by_uri.entry(uri.to_string()).or_default();
+        for word in vocabulary_words(text) {
+            *counts.entry(word.clone()).or_default() += 1;
+            *self.total.entry(word).or_default() += 1;
+        }
+    }
+
+    pub fn remove(&mut self, uri: &str, text: &str) {
+        let Some(counts) = self.by_uri.get_mut(uri) else {
+            return;
+        };
+        for word in vocabulary_words(text) {
+            let Some(count) = counts.get_mut(&word) else {
+                continue;
+            };
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&word);
+            }
+            if let Some(total) = self.total.get_mut(&word) {
+                *total -= 1;
+                if *total == 0 {
+                    self.total.remove(&word);
+                }
+            }
+        }
+        if counts.is_empty() {
+            self.by_uri.remove(uri);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.total.clear();
+        self.by_uri.clear();
+    }
+
+    /// How often `word` occurs in documents other than `uri`, ignoring case.
+    pub fn count_elsewhere(&self, word: &str, uri: &str) -> i32 {
+        let word = word.to_lowercase();
+        let own = self.by_uri.get(uri).and_then(|counts| counts.get(&word)).copied().unwrap_or(0);
+        self.total.get(&word).copied().unwrap_or(0) - own
+    }
+}
+
+pub fn append_to_dictionary(path: &Path, word: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", word)
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            current[j] = (previous[j] + 1).min(current[j - 1] + 1).min(previous[j - 1] + cost);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn match_case(original: &str, suggestion: &str) -> String {
+    if original.chars().all(|c| !c.is_lowercase()) {
+        return suggestion.to_uppercase();
+    }
+    if original.chars().next().is_some_and(|c| c.is_uppercase()) {
+        let mut chars = suggestion.chars();
+        if let Some(first) = chars.next() {
+            return first.to_uppercase().chain(chars).collect();
+        }
+    }
+    suggestion.to_string()
+}
+
+pub fn is_prose_file(file_uri: &str) -> bool {
+    PROSE_PATTERNS.iter().any(|p| file_uri.ends_with(p))
+}
+
+/// Splits an identifier at snake_case and camelCase boundaries, returning the
+/// byte offset of each piece within `word`.
+pub fn split_identifier(word: &str) -> Vec<(usize, &str)> {
+    let chars: Vec<(usize, char)> = word.char_indices().collect();
+    let mut pieces = Vec::new();
+    let mut start: Option<usize> = None;
+    for (i, &(byte, ch)) in chars.iter().enumerate() {
+        if !ch.is_alphabetic() {
+            if let Some(s) = start.take() {
+                pieces.push((s, &word[s..byte]));
+            }
+            continue;
+        }
+        if let Some(s) = start {
+            let previous = chars[i - 1].1;
+            let next_is_lower = chars.get(i + 1).is_some_and(|&(_, c)| c.is_lowercase());
+            let boundary = ch.is_uppercase() && (previous.is_lowercase() || (previous.is_uppercase() && next_is_lower));
+            if boundary {
+                pieces.push((s, &word[s..byte]));
+                start = Some(byte);
+            }
+        } else {
+            start = Some(byte);
+        }
+    }
+    if let Some(s) = start {
+        pieces.push((s, &word[s..]));
+    }
+    pieces
+}
+
+fn checked_regions(text: &str, file_uri: &str) -> Vec<(usize, usize)> {
+    if is_prose_file(file_uri) {
+        return vec![(0, text.len())];
+    }
+    match get_syntax(file_uri) {
+        Some(syntax) => non_code_spans(text, syntax)
+            .into_iter()
+            .map(|span| (span.start, span.end))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Returns the byte range and text of every unknown word in the comments and
+/// strings of `text`, or in the whole text for prose files.
+pub fn find_misspellings<F>(text: &str, file_uri: &str, is_known: F) -> Vec<(usize, usize, String)>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut result = Vec::new();
+    for (region_start, region_end) in checked_regions(text, file_uri) {
+        let region = &text[region_start..region_end];
+        let mut token_start: Option<usize> = None;
+        for (i, ch) in region.char_indices().chain([(region.len(), ' ')]) {
+            if valid_token_char(ch) {
+                token_start.get_or_insert(i);
+                continue;
+            }
+            let Some(start) = token_start.take() else {
+                continue;
+            };
+            let token = &region[start..i];
+            if token.chars().any(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            for (offset, piece) in split_identifier(token) {
+                if piece.chars().count() < MIN_SPELL_WORD_LEN || piece.chars().all(|c| c.is_uppercase()) {
+                    continue;
+                }
+                if !is_known(piece) {
+                    let begin = region_start + start + offset;
+                    result.push((begin, begin + piece.len(), piece.to_string()));
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary(words: &[&str]) -> Dictionary {
+        let mut dictionary = Dictionary::new();
+        for word in words {
+            dictionary.insert(word);
+        }
+        dictionary
+    }
+
+    #[test]
+    fn test_split_identifier() {
+        let pieces: Vec<&str> = split_identifier("parseHTTPResponse_body").into_iter().map(|(_, p)| p).collect();
+        assert_eq!(vec!["parse", "HTTP", "Response", "body"], pieces);
+
+        assert_eq!(vec![(0, "snake"), (6, "case")], split_identifier("snake_case"));
+        assert_eq!(vec![(0, "Camel"), (5, "Case")], split_identifier("CamelCase"));
+        assert_eq!(vec![(0, "word")], split_identifier("word"));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        let distance = |a: &str, b: &str| edit_distance(&a.chars().collect::<Vec<char>>(), &b.chars().collect::<Vec<char>>());
+        assert_eq!(0, distance("word", "word"));
+        assert_eq!(1, distance("wrd", "word"));
+        assert_eq!(3, distance("kitten", "sitting"));
+    }
+
+    #[test]
+    fn test_suggest() {
+        let dictionary = dictionary(&["receive", "recipe", "deceive", "banana"]);
+        assert_eq!(vec!["receive", "recipe"], dictionary.suggest("recieve", 3));
+        assert_eq!(vec!["Receive"], dictionary.suggest("Recieve", 1));
+        assert!(dictionary.suggest("xyz", 3).is_empty());
+        // words starting with another letter are not considered
+        assert!(dictionary.suggest("beceive", 3).is_empty());
+        assert!(dictionary.suggest("", 3).is_empty());
+    }
+
+    #[test]
+    fn test_load_base() {
+        let path = std::env::temp_dir().join("basecode-lsp-dictionary-test.txt");
+        fs::write(&path, "receive\n\nbanana\n").unwrap();
+        let mut dictionary = Dictionary::new();
+        assert!(!dictionary.load_base(&path.with_extension("missing")));
+        assert!(!dictionary.is_loaded());
+        assert!(dictionary.load_base(&path));
+        assert!(dictionary.is_loaded());
+        assert!(dictionary.contains("Banana"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_vocabulary() {
+        let mut vocabulary = Vocabulary::default();
+        vocabulary.add("file:///a.rs", "let parseCount = count_items(); // recieve recieve recieve");
+        vocabulary.add("file:///b.rs", "fn parse_count() -> Count {}");
+        assert_eq!(2, vocabulary.count_elsewhere("count", "file:///b.rs"));
+        assert_eq!(2, vocabulary.count_elsewhere("Count", "file:///a.rs"));
+        assert_eq!(0, vocabulary.count_elsewhere("recieve", "file:///a.rs"));
+        assert_eq!(3, vocabulary.count_elsewhere("RECIEVE", "file:///b.rs"));
+
+        vocabulary.remove("file:///a.rs", "let parseCount = count_items(); // recieve recieve recieve");
+        assert_eq!(0, vocabulary.count_elsewhere("recieve", "file:///b.rs"));
+        assert_eq!(2, vocabulary.count_elsewhere("count", "file:///a.rs"));
+        vocabulary.clear();
+        assert_eq!(0, vocabulary.count_elsewhere("count", "file:///a.rs"));
+    }
+
+    #[test]
+    fn test_find_misspellings() {
+        let dictionary = dictionary(&["the", "value", "parse", "count"]);
+        let is_known = |w: &str| dictionary.contains(w);
+
+        let text = "int parseCount = 1; // the vaule\nchar* s = \"teh value\";";
+        let misspellings = find_misspellings(text, "file:///main.c", is_known);
+        let words: Vec<&str> = misspellings.iter().map(|(_, _, w)| w.as_str()).collect();
+        assert_eq!(vec!["vaule", "teh"], words);
+        let (start, end, _) = &misspellings[0];
+        assert_eq!("vaule", &text[*start..*end]);
+
+        let misspellings = find_misspellings("the parseCuont", "file:///README.md", is_known);
+        assert_eq!(vec![(9, 14, "Cuont".to_string())], misspellings);
+
+        assert!(find_misspellings("teh", "file:///unknown", is_known).is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+use super::config::SymbolPatternConfig;
+use super::document::Document;
+use super::encoding::PositionEncoding;
+use regex::Regex;
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::*;
+
+const NAME_GROUP: &str = "name";
+const LEVEL_GROUP: &str = "level";
+const CODE_FENCES: [&str; 2] = ["```", "~~~"];
+
+struct Language {
+    files: &'static [&'static str],
+    patterns: &'static [(&'static str, SymbolKind)],
+    code_fences: bool,
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        files: &[".md", ".markdown"],
+        patterns: &[(r"^(?P<level>#{1,6})\s+(?P<name>.*?)[\s#]*$", SymbolKind::STRING)],
+        code_fences: true,
+    },
+    Language {
+        files: &[".sh", ".bash", ".zsh"],
+        patterns: &[
+            (r"^\s*function\s+(?P<name>[\w.:-]+)", SymbolKind::FUNCTION),
+            (r"^\s*(?P<name>[\w.:-]+)\s*\(\)", SymbolKind::FUNCTION),
+        ],
+        code_fences: false,
+    },
+    Language {
+        files: &["Makefile", "makefile", "GNUmakefile", ".mk"],
+        patterns: &[(r"^(?P<name>[^\s:=#.][^:=#]*?)\s*::?(?:[^=]|$)", SymbolKind::FUNCTION)],
+        code_fences: false,
+    },
+    Language {
+        files: &[".ini", ".cfg", ".toml", ".conf", ".desktop", ".service"],
+        patterns: &[(r"^\s*\[+(?P<name>[^\[\]]+)\]+", SymbolKind::NAMESPACE)],
+        code_fences: false,
+    },
+    Language {
+        files: &[".yaml", ".yml"],
+        patterns: &[(r#"^(?P<name>[\w.-]+|"[^"]*"|'[^']*')\s*:(?:\s|$)"#, SymbolKind::KEY)],
+        code_fences: false,
+    },
+];
+
+const SYMBOL_KINDS: &[(&str, SymbolKind)] = &[
+    ("file", SymbolKind::FILE),
+    ("module", SymbolKind::MODULE),
+    ("namespace", SymbolKind::NAMESPACE),
+    ("section", SymbolKind::NAMESPACE),
+    ("package", SymbolKind::PACKAGE),
+    ("class", SymbolKind::CLASS),
+    ("method", SymbolKind::METHOD),
+    ("property", SymbolKind::PROPERTY),
+    ("field", SymbolKind::FIELD),
+    ("constructor", SymbolKind::CONSTRUCTOR),
+    ("enum", SymbolKind::ENUM),
+    ("interface", SymbolKind::INTERFACE),
+    ("function", SymbolKind::FUNCTION),
+    ("variable", SymbolKind::VARIABLE),
+    ("constant", SymbolKind::CONSTANT),
+    ("heading", SymbolKind::STRING),
+    ("string", SymbolKind::STRING),
+    ("key", SymbolKind::KEY),
+    ("struct", SymbolKind::STRUCT),
+    ("event", SymbolKind::EVENT),
+    ("type", SymbolKind::TYPE_PARAMETER),
+];
+
+#[derive(Debug)]
+pub struct SymbolPattern {
+    files: Vec<String>,
+    regex: Regex,
+    kind: SymbolKind,
+}
+
+impl SymbolPattern {
+    fn new(files: Vec<String>, pattern: &str, kind: SymbolKind) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|e| format!("invalid symbol pattern {}: {}", pattern, e))?;
+        if !regex.capture_names().any(|name| name == Some(NAME_GROUP)) {
+            return Err(format!("symbol pattern {} has no (?P<{}>...) group", pattern, NAME_GROUP));
+        }
+        Ok(SymbolPattern { files, regex, kind })
+    }
+
+    pub fn from_config(config: &SymbolPatternConfig) -> Result<Self, String> {
+        let Some(&(_, kind)) = SYMBOL_KINDS.iter().find(|(name, _)| *name == config.kind) else {
+            return Err(format!("unknown symbol kind {}", config.kind));
+        };
+        SymbolPattern::new(config.files.clone(), &config.pattern, kind)
+    }
+
+    fn matches_file(&self, file_uri: &str) -> bool {
+        self.files.iter().any(|f| file_uri.ends_with(f.as_str()))
+    }
+}
+
+fn builtin_patterns() -> &'static Vec<SymbolPattern> {
+    static PATTERNS: OnceLock<Vec<SymbolPattern>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        let mut patterns = Vec::new();
+        for language in LANGUAGES {
+            let files: Vec<String> = language.files.iter().map(|f| f.to_string()).collect();
+            for (pattern, kind) in language.patterns {
+                patterns.push(SymbolPattern::new(files.clone(), pattern, *kind).unwrap());
+            }
+        }
+        patterns
+    })
+}
+
+fn uses_code_fences(file_uri: &str) -> bool {
+    LANGUAGES
+        .iter()
+        .any(|language| language.code_fences && language.files.iter().any(|f| file_uri.ends_with(f)))
+}
+
+struct Entry {
+    name: String,
+    kind: SymbolKind,
+    depth: usize,
+    range: Range,
+    selection_range: Range,
+}
+
+fn find_entries(document: &Document, patterns: &[&SymbolPattern], fences: bool, encoding: PositionEncoding) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut in_fence = false;
+    for n in 0..document.line_count() {
+        let line = document.line(n).unwrap_or_default();
+        if fences && CODE_FENCES.iter().any(|fence| line.trim_start().starts_with(fence)) {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let Some((captures, kind)) = patterns.iter().find_map(|p| p.regex.captures(line).map(|c| (c, p.kind))) else {
+            continue;
+        };
+        let Some(name) = captures.name(NAME_GROUP).filter(|name| !name.as_str().trim().is_empty()) else {
+            continue;
+        };
+        let depth = match captures.name(LEVEL_GROUP) {
+            Some(level) => level.as_str().chars().count(),
+            None => line.chars().take_while(|c| c.is_whitespace()).count(),
+        };
+        let line_number = n as u32;
+        entries.push(Entry {
+            name: name.as_str().trim().to_string(),
+            kind,
+            depth,
+            range: Range {
+                start: Position::new(line_number, 0),
+                end: Position::new(line_number, encoding.len(line)),
+            },
+            selection_range: Range {
+                start: Position::new(line_number, encoding.column(line, name.start())),
+                end: Position::new(line_number, encoding.column(line, name.end())),
+            },
+        });
+    }
+
+    // a symbol extends up to the next symbol at the same or a shallower depth
+    for i in 0..entries.len() {
+        let next = entries[i + 1..].iter().find(|e| e.depth <= entries[i].depth);
+        let end_line = match next {
+            Some(next) => next.range.start.line.saturating_sub(1),
+            None => document.line_count().saturating_sub(1) as u32,
+        };
+        let end_line = end_line.max(entries[i].range.start.line);
+        let end_text = document.line(end_line as usize).unwrap_or_default();
+        entries[i].range.end = Position::new(end_line, encoding.len(end_text));
+    }
+    entries
+}
+
+#[allow(deprecated)]
+fn build_tree(entries: &[Entry], index: &mut usize, parent_depth: Option<usize>) -> Vec<DocumentSymbol> {
+    let mut symbols = Vec::new();
+    while let Some(entry) = entries.get(*index) {
+        if parent_depth.is_some_and(|depth| entry.depth <= depth) {
+            break;
+        }
+        *index += 1;
+        let children = build_tree(entries, index, Some(entry.depth));
+        symbols.push(DocumentSymbol {
+            name: entry.name.clone(),
+            detail: None,
+            kind: entry.kind,
+            tags: None,
+            deprecated: None,
+            range: entry.range,
+            selection_range: entry.selection_range,
+            children: (!children.is_empty()).then_some(children),
+        });
+    }
+    symbols
+}
+
+/// Symbols for `uri` from the user patterns followed by the built-in
+/// table. The first pattern that matches a line wins.
+pub fn document_symbols(
+    document: &Document,
+    uri: &Url,
+    user_patterns: &[SymbolPattern],
+    hierarchical: bool,
+    encoding: PositionEncoding,
+) -> DocumentSymbolResponse {
+    let file_uri = uri.as_str();
+    let patterns: Vec<&SymbolPattern> = user_patterns
+        .iter()
+        .chain(builtin_patterns().iter())
+        .filter(|p| p.matches_file(file_uri))
+        .collect();
+    let entries = find_entries(document, &patterns, uses_code_fences(file_uri), encoding);
+    if hierarchical {
+        return DocumentSymbolResponse::Nested(build_tree(&entries, &mut 0, None));
+    }
+    #[allow(deprecated)]
+    let symbols = entries
+        .into_iter()
+        .map(|entry| SymbolInformation {
+            name: entry.name,
+            kind: entry.kind,
+            tags: None,
+            deprecated: None,
+            location: Location::new(uri.clone(), entry.selection_range),
+            container_name: None,
+        })
+        .collect();
+    DocumentSymbolResponse::Flat(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested(text: &str, file: &str, user_patterns: &[SymbolPattern]) -> Vec<DocumentSymbol> {
+        let uri = Url::parse(&format!("file:///tmp/{}", file)).unwrap();
+        let document = Document::new(text.to_string());
+        match document_symbols(&document, &uri, user_patterns, true, PositionEncoding::Utf16) {
+            DocumentSymbolResponse::Nested(symbols) => symbols,
+            DocumentSymbolResponse::Flat(_) => panic!("expected nested symbols"),
+        }
+    }
+
+    fn names(symbols: &[DocumentSymbol]) -> Vec<&str> {
+        symbols.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_markdown_headings() {
+        let text = "# Title\nintro\n## Install ##\n```sh\n# not a heading\n```\n## Usage\n# Other";
+        let symbols = nested(text, "README.md", &[]);
+        assert_eq!(vec!["Title", "Other"], names(&symbols));
+        let children = symbols[0].children.as_ref().unwrap();
+        assert_eq!(vec!["Install", "Usage"], names(children));
+        assert_eq!(Range::new(Position::new(2, 3), Position::new(2, 10)), children[0].selection_range);
+        assert_eq!(Position::new(5, 3), children[0].range.end);
+        assert_eq!(Position::new(6, 8), symbols[0].range.end);
+    }
+
+    #[test]
+    fn test_builtin_languages() {
+        let text = "build() {\n  make\n}\nfunction deploy {\n}";
+        assert_eq!(vec!["build", "deploy"], names(&nested(text, "run.sh", &[])));
+
+        let text = ".PHONY: all\nCC := gcc\nall: main.o\n\tcc main.o\nmain.o: main.c";
+        assert_eq!(vec!["all", "main.o"], names(&nested(text, "Makefile", &[])));
+
+        let text = "[core]\nkey = 1\n[[bin]]\nname = \"x\"";
+        assert_eq!(vec!["core", "bin"], names(&nested(text, "config.toml", &[])));
+
+        let text = "---\nname: ci\non:\n  push:\njobs:\n  build: {}";
+        assert_eq!(vec!["name", "on", "jobs"], names(&nested(text, "ci.yml", &[])));
+
+        assert!(nested("fn main() {}", "main.rs", &[]).is_empty());
+    }
+
+    #[test]
+    fn test_user_patterns() {
+        let config = SymbolPatternConfig {
+            files: vec![".rs".to_string()],
+            pattern: r"^\s*fn\s+(?P<name>\w+)".to_string(),
+            kind: "function".to_string(),
+        };
+        let patterns = vec![SymbolPattern::from_config(&config).unwrap()];
+        let symbols = nested("fn main() {\n    fn helper() {}\n}", "main.rs", &patterns);
+        assert_eq!(vec!["main"], names(&symbols));
+        assert_eq!(vec!["helper"], names(symbols[0].children.as_ref().unwrap()));
+
+        let invalid = SymbolPatternConfig {
+            kind: "gadget".to_string(),
+            ..config.clone()
+        };
+        assert!(SymbolPattern::from_config(&invalid).is_err());
+        let invalid = SymbolPatternConfig {
+            pattern: r"^fn (\w+)".to_string(),
+            ..config
+        };
+        assert!(SymbolPattern::from_config(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_flat_symbols() {
+        let uri = Url::parse("file:///tmp/README.md").unwrap();
+        let document = Document::new("# A\n## B".to_string());
+        let DocumentSymbolResponse::Flat(symbols) =
+            document_symbols(&document, &uri, &[], false, PositionEncoding::Utf16)
+        else {
+            panic!("expected flat symbols");
+        };
+        assert_eq!(2, symbols.len());
+        assert_eq!(uri, symbols[1].location.uri);
+    }
+}
@@ -0,0 +1,411 @@
+use super::document::Document;
+use super::util::valid_token_char;
+use hashbrown::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use tower_lsp::lsp_types::CompletionItemKind;
+
+const TAG_FILES: [&str; 4] = ["tags", ".tags", "TAGS", ".git/tags"];
+/// How long a root's tags file is trusted before it is looked up again.
+const TAGS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// The most tags offered for one completion.
+const MAX_TAG_SUGGESTIONS: usize = 200;
+const ETAGS_SECTION: char = '\x0c';
+const ETAGS_TEXT_END: char = '\x7f';
+const ETAGS_NAME_END: char = '\x01';
+
+const TAG_KINDS: &[(&[&str], CompletionItemKind)] = &[
+    (&["f", "function", "p", "prototype", "subroutine"], CompletionItemKind::FUNCTION),
+    (&["m", "method", "member"], CompletionItemKind::METHOD),
+    (&["c", "class"], CompletionItemKind::CLASS),
+    (&["s", "struct", "u", "union"], CompletionItemKind::STRUCT),
+    (&["g", "enum"], CompletionItemKind::ENUM),
+    (&["e", "enumerator"], CompletionItemKind::ENUM_MEMBER),
+    (&["i", "interface", "trait"], CompletionItemKind::INTERFACE),
+    (&["n", "namespace", "module", "package"], CompletionItemKind::MODULE),
+    (&["d", "macro", "define"], CompletionItemKind::CONSTANT),
+    (&["constant", "const"], CompletionItemKind::CONSTANT),
+    (&["v", "variable", "l", "local", "x", "externvar"], CompletionItemKind::VARIABLE),
+    (&["field", "property"], CompletionItemKind::FIELD),
+    (&["t", "typedef", "type", "alias"], CompletionItemKind::TYPE_PARAMETER),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagAddress {
+    Line(u32),
+    Pattern(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub file: PathBuf,
+    pub address: TagAddress,
+    pub kind: Option<String>,
+}
+
+impl Tag {
+    pub fn completion_kind(&self) -> CompletionItemKind {
+        let kind = self.kind.as_deref().unwrap_or_default();
+        TAG_KINDS
+            .iter()
+            .find(|(names, _)| names.contains(&kind))
+            .map_or(CompletionItemKind::REFERENCE, |(_, kind)| *kind)
+    }
+
+    /// Zero based line and byte column of the tag in `document`, the contents
+    /// of `self.file`.
+    pub fn locate(&self, document: &Document) -> Option<(usize, usize)> {
+        let (line_number, line) = match &self.address {
+            TagAddress::Line(n) => {
+                let n = n.saturating_sub(1) as usize;
+                (n, document.line(n)?)
+            }
+            TagAddress::Pattern(pattern) => (0..document.line_count())
+                .filter_map(|n| Some((n, document.line(n)?)))
+                .find(|(_, line)| pattern_matches(pattern, line))?,
+        };
+        Some((line_number, line.find(self.name.as_str()).unwrap_or(0)))
+    }
+}
+
+fn pattern_matches(pattern: &str, line: &str) -> bool {
+    let (anchored_start, pattern) = match pattern.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+    let (anchored_end, pattern) = match pattern.strip_suffix('$') {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+    match (anchored_start, anchored_end) {
+        (true, true) => line == pattern,
+        (true, false) => line.starts_with(pattern),
+        (false, true) => line.ends_with(pattern),
+        (false, false) => line.contains(pattern),
+    }
+}
+
+fn unescape_pattern(pattern: &str) -> String {
+    let mut result = String::new();
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            if let Some(next) = chars.next() {
+                result.push(next);
+            }
+        } else {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+fn parse_address(address: &str) -> Option<TagAddress> {
+    let address = address.trim_end_matches(";\"");
+    if let Ok(line) = address.parse::<u32>() {
+        return Some(TagAddress::Line(line));
+    }
+    let delimiter = address.chars().next().filter(|c| *c == '/' || *c == '?')?;
+    let pattern = address[1..].strip_suffix(delimiter)?;
+    Some(TagAddress::Pattern(unescape_pattern(pattern)))
+}
+
+/// Parses an Exuberant or Universal ctags file. Relative file names are
+/// resolved against `base`, the directory of the tags file.
+pub fn parse_ctags(content: &str, base: &Path) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    for line in content.lines() {
+        if line.starts_with("!_TAG_") {
+            continue;
+        }
+        let Some((name, rest)) = line.split_once('\t') else {
+            continue;
+        };
+        let Some((file, rest)) = rest.split_once('\t') else {
+            continue;
+        };
+        let (address, fields) = match rest.find(";\"\t") {
+            Some(index) => (&rest[..index], &rest[index + 3..]),
+            None => (rest.trim_end_matches(";\""), ""),
+        };
+        let Some(mut address) = parse_address(address) else {
+            continue;
+        };
+        let mut kind = None;
+        for field in fields.split('\t').filter(|f| !f.is_empty()) {
+            match field.split_once(':') {
+                Some(("kind", value)) => kind = Some(value.to_string()),
+                Some(("line", value)) => {
+                    if let Ok(n) = value.parse() {
+                        address = TagAddress::Line(n);
+                    }
+                }
+                Some(_) => {}
+                None => kind = Some(field.to_string()),
+            }
+        }
+        tags.push(Tag {
+            name: name.to_string(),
+            file: base.join(file),
+            address,
+            kind,
+        });
+    }
+    tags
+}
+
+fn etags_implicit_name(text: &str) -> Option<&str> {
+    let text = text.trim_end_matches(|c: char| !valid_token_char(c));
+    let start = text.rfind(|c: char| !valid_token_char(c)).map_or(0, |i| i + 1);
+    let name = &text[start..];
+    (!name.is_empty()).then_some(name)
+}
+
+/// Parses an Emacs etags `TAGS` file.
+pub fn parse_etags(content: &str, base: &Path) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    for section in content.split(ETAGS_SECTION) {
+        let mut lines = section.lines().filter(|line| !line.is_empty());
+        let Some((file, _)) = lines.next().and_then(|header| header.rsplit_once(',')) else {
+            continue;
+        };
+        let file = base.join(file);
+        for line in lines {
+            let Some((text, rest)) = line.split_once(ETAGS_TEXT_END) else {
+                continue;
+            };
+            let (name, position) = match rest.split_once(ETAGS_NAME_END) {
+                Some((name, position)) => (Some(name), position),
+                None => (etags_implicit_name(text), rest),
+            };
+            let Some(name) = name else {
+                continue;
+            };
+            let address = match position.split(',').next().and_then(|n| n.parse().ok()) {
+                Some(line) => TagAddress::Line(line),
+                None => TagAddress::Pattern(format!("^{}", text)),
+            };
+            tags.push(Tag {
+                name: name.to_string(),
+                file: file.clone(),
+                address,
+                kind: None,
+            });
+        }
+    }
+    tags
+}
+
+pub fn find_tags_file(root: &Path) -> Option<PathBuf> {
+    TAG_FILES.iter().map(|name| root.join(name)).find(|path| path.is_file())
+}
+
+pub fn load_tags(path: &Path) -> Vec<Tag> {
+    let Ok(content) = fs::read(path) else {
+        return Vec::new();
+    };
+    let content = String::from_utf8_lossy(&content);
+    let mut base = path.parent().unwrap_or(Path::new(""));
+    // .git/tags is generated from the work tree root
+    if base.file_name().is_some_and(|name| name == ".git") {
+        base = base.parent().unwrap_or(base);
+    }
+    if content.starts_with(ETAGS_SECTION) {
+        parse_etags(&content, base)
+    } else {
+        parse_ctags(&content, base)
+    }
+}
+
+/// The tags loaded from the tags file of one root.
+#[derive(Debug)]
+struct TagFile {
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+    names: Vec<String>,
+    tags: HashMap<String, Vec<Tag>>,
+}
+
+impl TagFile {
+    fn new(checked: Instant) -> Self {
+        TagFile {
+            path: None,
+            modified: None,
+            checked,
+            names: Vec::new(),
+            tags: HashMap::new(),
+        }
+    }
+}
+
+/// The tags of every root a session completed in, so that documents of
+/// different roots do not reload each other's tags file.
+#[derive(Debug, Default)]
+pub struct TagIndex {
+    files: HashMap<PathBuf, TagFile>,
+}
+
+impl TagIndex {
+    pub fn new() -> Self {
+        TagIndex::default()
+    }
+
+    /// Whether the tags file of `root` is due to be looked up on disk, which
+    /// happens at most every `TAGS_CHECK_INTERVAL`. Records the check.
+    pub fn is_due(&mut self, root: &Path, now: Instant) -> bool {
+        match self.files.get_mut(root) {
+            Some(file) if now.duration_since(file.checked) < TAGS_CHECK_INTERVAL => false,
+            Some(file) => {
+                file.checked = now;
+                true
+            }
+            None => {
+                self.files.insert(root.to_path_buf(), TagFile::new(now));
+                true
+            }
+        }
+    }
+
+    /// Whether `path`, modified at `modified`, differs from the file loaded
+    /// for `root`.
+    pub fn is_stale(&self, root: &Path, path: Option<&PathBuf>, modified: Option<SystemTime>) -> bool {
+        self.files.get(root).is_none_or(|file| file.path.as_ref() != path || file.modified != modified)
+    }
+
+    pub fn replace(&mut self, root: &Path, path: Option<PathBuf>, modified: Option<SystemTime>, tags: Vec<Tag>) {
+        let file = self.files.entry(root.to_path_buf()).or_insert_with(|| TagFile::new(Instant::now()));
+        file.path = path;
+        file.modified = modified;
+        file.tags.clear();
+        for tag in tags {
+            file.tags.entry(tag.name.clone()).or_default().push(tag);
+        }
+        file.names = file.tags.keys().cloned().collect();
+        file.names.sort();
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.values().flat_map(|file| file.tags.values()).map(|tags| tags.len()).sum()
+    }
+
+    pub fn lookup(&self, root: &Path, name: &str) -> &[Tag] {
+        self.files.get(root).and_then(|file| file.tags.get(name)).map_or(&[], |tags| tags.as_slice())
+    }
+
+    pub fn suggest(&self, root: &Path, prefix: &str) -> Vec<(String, CompletionItemKind)> {
+        let Some(file) = self.files.get(root) else {
+            return Vec::new();
+        };
+        let start = file.names.partition_point(|name| name.as_str() < prefix);
+        file.names[start..]
+            .iter()
+            .take_while(|name| name.starts_with(prefix))
+            .take(MAX_TAG_SUGGESTIONS)
+            .map(|name| (name.clone(), file.tags[name][0].completion_kind()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTAGS: &str = "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+        main\tsrc/main.c\t/^int main(int argc, char **argv)$/;\"\tf\n\
+        Point\tsrc/point.h\t12;\"\tkind:struct\tline:12\n\
+        MAX_SIZE\tsrc/point.h\t/^#define MAX_SIZE 10$/;\"\td\n\
+        path_join\tlib/path.c\t/^char \\/* x *\\/ path_join(void)$/;\"\tfunction\n";
+
+    #[test]
+    fn test_parse_ctags() {
+        let tags = parse_ctags(CTAGS, Path::new("/project"));
+        assert_eq!(4, tags.len());
+        assert_eq!(PathBuf::from("/project/src/main.c"), tags[0].file);
+        assert_eq!(TagAddress::Pattern("^int main(int argc, char **argv)$".to_string()), tags[0].address);
+        assert_eq!(CompletionItemKind::FUNCTION, tags[0].completion_kind());
+        assert_eq!(TagAddress::Line(12), tags[1].address);
+        assert_eq!(CompletionItemKind::STRUCT, tags[1].completion_kind());
+        assert_eq!(CompletionItemKind::CONSTANT, tags[2].completion_kind());
+        assert_eq!(TagAddress::Pattern("^char /* x */ path_join(void)$".to_string()), tags[3].address);
+    }
+
+    #[test]
+    fn test_parse_etags() {
+        let content = "\x0c\nsrc/main.c,60\nint main(\x7f3,20\nstatic int count\x7fcount\x015,40\n\x0c\nlib.el,10\n(defun helper\x7f1,0\n";
+        let tags = parse_etags(content, Path::new("/project"));
+        let names: Vec<&str> = tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert_eq!(vec!["main", "count", "helper"], names);
+        assert_eq!(TagAddress::Line(5), tags[1].address);
+        assert_eq!(PathBuf::from("/project/lib.el"), tags[2].file);
+    }
+
+    #[test]
+    fn test_locate() {
+        let tags = parse_ctags(CTAGS, Path::new("/project"));
+        let document = Document::new("#include <stdio.h>\n\nint main(int argc, char **argv)\n{\n}".to_string());
+        assert_eq!(Some((2, 4)), tags[0].locate(&document));
+        assert_eq!(None, tags[2].locate(&document));
+        let tag = Tag {
+            address: TagAddress::Line(1),
+            ..tags[0].clone()
+        };
+        assert_eq!(Some((0, 0)), tag.locate(&document));
+
+        let document = Document::new("#include <stdio.h>\r\rint main(int argc, char **argv)\r{\r}".to_string());
+        assert_eq!(Some((2, 4)), tags[0].locate(&document));
+        let tag = Tag {
+            address: TagAddress::Line(3),
+            ..tags[0].clone()
+        };
+        assert_eq!(Some((2, 4)), tag.locate(&document));
+    }
+
+    #[test]
+    fn test_tag_index() {
+        let mut index = TagIndex::new();
+        let root = Path::new("/project");
+        let path = Some(PathBuf::from("/project/tags"));
+        assert!(index.is_stale(root, path.as_ref(), None));
+        index.replace(root, path.clone(), None, parse_ctags(CTAGS, root));
+        assert!(!index.is_stale(root, path.as_ref(), None));
+        assert_eq!(4, index.len());
+        assert_eq!(vec![("MAX_SIZE".to_string(), CompletionItemKind::CONSTANT)], index.suggest(root, "MA"));
+        assert_eq!(1, index.lookup(root, "Point").len());
+        assert!(index.lookup(root, "missing").is_empty());
+
+        // another root keeps its own tags
+        let other = Path::new("/other");
+        index.replace(other, Some(PathBuf::from("/other/tags")), None, Vec::new());
+        assert!(index.suggest(other, "MA").is_empty());
+        assert_eq!(1, index.suggest(root, "MA").len());
+        assert!(!index.is_stale(root, path.as_ref(), None));
+    }
+
+    #[test]
+    fn test_tag_index_is_due() {
+        let mut index = TagIndex::new();
+        let root = Path::new("/project");
+        let now = Instant::now();
+        assert!(index.is_due(root, now));
+        assert!(!index.is_due(root, now + Duration::from_secs(1)));
+        assert!(index.is_due(root, now + TAGS_CHECK_INTERVAL));
+        assert!(!index.is_due(root, now + TAGS_CHECK_INTERVAL));
+        assert!(index.is_due(Path::new("/other"), now));
+    }
+
+    #[test]
+    fn test_tag_index_suggest_limit() {
+        let mut index = TagIndex::new();
+        let root = Path::new("/project");
+        let content: String = (0..MAX_TAG_SUGGESTIONS + 10).map(|i| format!("name{}\tmain.c\t{}\n", i, i + 1)).collect();
+        index.replace(root, None, None, parse_ctags(&content, root));
+        assert_eq!(MAX_TAG_SUGGESTIONS + 10, index.len());
+        assert_eq!(MAX_TAG_SUGGESTIONS, index.suggest(root, "name").len());
+    }
+}
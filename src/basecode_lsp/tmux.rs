@@ -1,13 +1,16 @@
 use regex::Regex;
 use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use super::config::Settings;
 use super::util::*;
+use tokio::sync::Mutex;
+use tokio::sync::Notify;
 
 fn is_tmux_executable() -> bool {
     let output = Command::new("tmux").arg("-V").output();
-    match output {
-        Ok(_) => true,
-        Err(_) => false,
-    }
+    output.is_ok()
 }
 
 fn list_tmux_panes() -> Vec<String> {
@@ -67,17 +70,56 @@ pub fn retrieve_tmux_words(min_len: usize) -> Vec<String> {
         }
     }
 
-    result = result
-        .into_iter()
-        .filter(|s| is_token(&s.chars().collect(), min_len))
-        .collect();
+    result.retain(|s| is_token(&s.chars().collect::<Vec<char>>(), min_len));
     result.sort();
     result.dedup();
     result
 }
 
+pub async fn scrape_tmux_words(min_len: usize) -> Vec<String> {
+    tokio::task::spawn_blocking(move || retrieve_tmux_words(min_len))
+        .await
+        .unwrap_or_default()
+}
+
+/// Refreshes `words` from tmux each time `trigger` fires, waiting for the
+/// triggers to settle for `tmux_debounce` milliseconds and never scraping more
+/// often than once every `tmux_interval` seconds. `settings` are those of the
+/// session that triggered last, which daemon sessions set before triggering.
+pub async fn refresh_tmux_words(trigger: Arc<Notify>, settings: Arc<Mutex<Settings>>, words: Arc<Mutex<Vec<String>>>) {
+    let mut last_refresh: Option<Instant> = None;
+    loop {
+        trigger.notified().await;
+        let current = settings.lock().await.clone();
+        let debounce = Duration::from_millis(current.tmux_debounce);
+        loop {
+            tokio::select! {
+                _ = trigger.notified() => continue,
+                _ = tokio::time::sleep(debounce) => break,
+            }
+        }
+
+        let interval = Duration::from_secs(current.tmux_interval);
+        if let Some(elapsed) = last_refresh.map(|last| last.elapsed()) {
+            if elapsed < interval {
+                tokio::time::sleep(interval - elapsed).await;
+            }
+        }
+
+        if !current.tmux_source {
+            words.lock().await.clear();
+            continue;
+        }
+        let tmux_words = scrape_tmux_words(current.min_word_len).await;
+        *words.lock().await = tmux_words;
+        last_refresh = Some(Instant::now());
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn test_is_tmux_executable() {
         println!("{:?}", super::is_tmux_executable());
@@ -111,6 +153,32 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_refresh_tmux_words_disabled() {
+        let trigger = Arc::new(Notify::new());
+        let settings = Settings {
+            tmux_source: false,
+            tmux_debounce: 0,
+            ..Settings::default()
+        };
+        let words = Arc::new(Mutex::new(vec!["stale".to_string()]));
+        let task = tokio::spawn(refresh_tmux_words(
+            trigger.clone(),
+            Arc::new(Mutex::new(settings)),
+            words.clone(),
+        ));
+
+        trigger.notify_one();
+        for _ in 0..100 {
+            if words.lock().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        task.abort();
+        assert!(words.lock().await.is_empty());
+    }
+
     #[test]
     fn test_retrieve_tmux_words() {
         if super::is_tmux_executable() {
@@ -0,0 +1,180 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::net::TcpListener;
+use tokio::net::UnixListener;
+use tokio::net::UnixStream;
+use tokio::sync::Notify;
+
+pub const TCP_HOST: &str = "127.0.0.1";
+
+pub type Connection = (Box<dyn AsyncRead + Unpin + Send>, Box<dyn AsyncWrite + Unpin + Send>);
+
+/// Where the server accepts LSP connections.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Listen {
+    Stdio,
+    Tcp(u16),
+    Unix(PathBuf),
+}
+
+impl FromStr for Listen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "stdio" => Ok(Listen::Stdio),
+            Some(("tcp", port)) => port.parse().map(Listen::Tcp).map_err(|_| format!("invalid port {}", port)),
+            Some(("unix", path)) if !path.is_empty() => Ok(Listen::Unix(PathBuf::from(path))),
+            _ => Err(format!("expected stdio, tcp:PORT or unix:PATH, got {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub async fn bind(listen: &Listen) -> io::Result<Listener> {
+        match listen {
+            Listen::Stdio => Err(io::Error::new(io::ErrorKind::InvalidInput, "stdio is not a socket")),
+            Listen::Tcp(port) => Ok(Listener::Tcp(TcpListener::bind((TCP_HOST, *port)).await?)),
+            Listen::Unix(path) => Ok(Listener::Unix(bind_unix(path).await?)),
+        }
+    }
+
+    /// The address clients connect to, e.g. `tcp:9257`.
+    pub fn address(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map_or("tcp".to_string(), |address| format!("tcp:{}", address.port())),
+            Listener::Unix(listener) => listener
+                .local_addr()
+                .ok()
+                .and_then(|address| address.as_pathname().map(|path| format!("unix:{}", path.display())))
+                .unwrap_or("unix".to_string()),
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                let (input, output) = stream.into_split();
+                Ok((Box::new(input), Box::new(output)))
+            }
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                let (input, output) = stream.into_split();
+                Ok((Box::new(input), Box::new(output)))
+            }
+        }
+    }
+}
+
+/// Notifies `closed` once the client hung up. tower-lsp keeps serving while a
+/// handler waits for a reply from the client, so sessions end on this instead.
+#[derive(Debug)]
+pub struct ClosingReader<R> {
+    inner: R,
+    closed: Arc<Notify>,
+}
+
+impl<R> ClosingReader<R> {
+    pub fn new(inner: R, closed: Arc<Notify>) -> Self {
+        ClosingReader { inner, closed }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ClosingReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        match &poll {
+            Poll::Ready(Ok(())) if buf.filled().len() == filled => self.closed.notify_one(),
+            Poll::Ready(Err(_)) => self.closed.notify_one(),
+            _ => {}
+        }
+        poll
+    }
+}
+
+/// Binds a Unix socket at `path`, replacing the socket file a server that is
+/// no longer running left behind. Other files are never removed.
+pub async fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is not a socket", path.display())));
+        }
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use", path.display())));
+        }
+        fs::remove_file(path)?;
+    }
+    // create the socket accessible to the current user only
+    // SAFETY: umask has no preconditions; the previous mask is restored right after
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    listener
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_parse_listen() {
+        assert_eq!(Ok(Listen::Stdio), "stdio".parse());
+        assert_eq!(Ok(Listen::Tcp(9257)), "tcp:9257".parse());
+        assert_eq!(Ok(Listen::Unix(PathBuf::from("/tmp/basecode.sock"))), "unix:/tmp/basecode.sock".parse());
+        assert!("tcp:http".parse::<Listen>().is_err());
+        assert!("unix:".parse::<Listen>().is_err());
+        assert!("pipe:name".parse::<Listen>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_closing_reader() {
+        use tokio::io::AsyncReadExt;
+        let closed = Arc::new(Notify::new());
+        let mut reader = ClosingReader::new(&b"Content-Length: 2\r\n\r\n{}"[..], closed.clone());
+        let mut content = String::new();
+        reader.read_to_string(&mut content).await.unwrap();
+        assert!(content.ends_with("{}"));
+        tokio::time::timeout(std::time::Duration::from_secs(1), closed.notified()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_unix() {
+        let folder = std::env::temp_dir().join("basecode-lsp-transport-test");
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("server.sock");
+        let _ = fs::remove_file(&path);
+
+        let listener = bind_unix(&path).await.unwrap();
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+        assert_eq!(io::ErrorKind::AddrInUse, bind_unix(&path).await.unwrap_err().kind());
+        // a stale socket file is replaced
+        drop(listener);
+        assert!(bind_unix(&path).await.is_ok());
+
+        let file = folder.join("regular");
+        fs::write(&file, "keep").unwrap();
+        assert_eq!(io::ErrorKind::AlreadyExists, bind_unix(&file).await.unwrap_err().kind());
+        assert!(file.exists());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
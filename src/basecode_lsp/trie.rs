@@ -1,4 +1,5 @@
 use hashbrown::HashMap;
+use hashbrown::HashSet;
 use std::cmp::max;
 
 #[derive(Debug, Default)]
@@ -8,6 +9,13 @@ pub struct TrieNode {
     uri: String,
 }
 
+#[derive(Debug, Default, PartialEq)]
+pub struct TrieStats {
+    pub words: usize,
+    pub occurrences: usize,
+    pub nodes: usize,
+}
+
 #[derive(Debug)]
 pub struct Trie {
     root: TrieNode,
@@ -53,6 +61,20 @@ impl Trie {
         false
     }
 
+    pub fn stats(&self) -> TrieStats {
+        let mut stats = TrieStats::default();
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            stats.nodes += 1;
+            if node.word_count > 0 {
+                stats.words += 1;
+                stats.occurrences += node.word_count as usize;
+            }
+            stack.extend(node.children.values());
+        }
+        stats
+    }
+
     pub fn suggest_completions(&self, prefix: &str) -> Vec<(String, String)> {
         let mut completions = Vec::new();
         let p: Vec<char> = prefix.chars().collect();
@@ -95,6 +117,44 @@ impl Trie {
     }
 }
 
+/// The tries of the sessions sharing a word index, keyed by session. Words
+/// of every session are suggested, but a session only resets its own.
+#[derive(Debug, Default)]
+pub struct WordIndex {
+    tries: HashMap<usize, Trie>,
+}
+
+impl WordIndex {
+    pub fn new() -> Self {
+        WordIndex::default()
+    }
+
+    pub fn trie(&mut self, session: usize) -> &mut Trie {
+        self.tries.entry(session).or_insert_with(Trie::new)
+    }
+
+    pub fn clear(&mut self, session: usize) {
+        self.tries.remove(&session);
+    }
+
+    pub fn stats(&self, session: usize) -> TrieStats {
+        self.tries.get(&session).map_or(TrieStats::default(), Trie::stats)
+    }
+
+    pub fn suggest_completions(&self, prefix: &str) -> Vec<(String, String)> {
+        let mut seen = HashSet::new();
+        let mut completions = Vec::new();
+        for trie in self.tries.values() {
+            for (word, uri) in trie.suggest_completions(prefix) {
+                if seen.insert(word.clone()) {
+                    completions.push((word, uri));
+                }
+            }
+        }
+        completions
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -103,7 +163,7 @@ mod tests {
         let mut it = &trie.root;
         for ch in word.chars() {
             if let Some(node) = it.children.get(&ch) {
-                it = &node;
+                it = node;
             } else {
                 return false;
             }
@@ -187,6 +247,20 @@ mod tests {
         assert_eq!(completions, Vec::<(String, String)>::new());
     }
 
+    #[test]
+    fn test_stats() {
+        let mut trie = Trie::new();
+        assert_eq!(TrieStats { words: 0, occurrences: 0, nodes: 1 }, trie.stats());
+
+        trie.insert("ab", "file://");
+        trie.insert("ab", "file://");
+        trie.insert("ac", "file://");
+        assert_eq!(TrieStats { words: 2, occurrences: 3, nodes: 4 }, trie.stats());
+
+        trie.remove("ac");
+        assert_eq!(TrieStats { words: 1, occurrences: 2, nodes: 3 }, trie.stats());
+    }
+
     #[test]
     fn test_remove_nonexistent() {
         let mut trie = Trie::new();
@@ -209,4 +283,23 @@ mod tests {
         trie.remove("apple");
         assert!(!trie_contains(&trie, "apple"));
     }
+
+    #[test]
+    fn test_word_index_sessions() {
+        let mut index = WordIndex::new();
+        index.trie(1).insert("shared", "file:///a");
+        index.trie(1).insert("first", "file:///a");
+        index.trie(2).insert("shared", "file:///b");
+        index.trie(2).insert("second", "file:///b");
+        let mut words: Vec<String> = index.suggest_completions("s").into_iter().map(|(word, _)| word).collect();
+        words.sort();
+        assert_eq!(vec!["second", "shared"], words);
+
+        // clearing one session keeps the words of the other
+        index.clear(1);
+        assert!(index.suggest_completions("f").is_empty());
+        assert_eq!(1, index.suggest_completions("sh").len());
+        assert_eq!(TrieStats::default(), index.stats(1));
+        assert_eq!(2, index.stats(2).words);
+    }
 }
@@ -0,0 +1,208 @@
+use super::snippet::parse_extends;
+use super::snippet::parse_header;
+use super::snippet::Snippet;
+use super::snippet::SnippetFile;
+use super::snippet::SnippetSyntax;
+
+const END_SNIPPET: &str = "endsnippet";
+const END_GLOBAL: &str = "endglobal";
+const PYTHON_INTERPOLATION: &str = "`!p";
+/// Snippet actions that run Python code before or after expanding.
+const PYTHON_ACTIONS: [&str; 4] = ["context", "pre_expand", "post_expand", "post_jump"];
+
+/// Whether a `.snippets` file is written for UltiSnips rather than SnipMate,
+/// which share the extension.
+pub fn is_ultisnips(content: &str) -> bool {
+    content.lines().any(|line| line.trim_end() == END_SNIPPET)
+}
+
+/// The index of the backtick closing an interpolation in `code`.
+fn closing_backtick(code: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, ch) in code.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match ch {
+            '\\' => escaped = true,
+            '`' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Removes `` `!p ...` `` interpolations from a body, which we cannot run.
+/// Returns whether any was removed.
+fn strip_python(body: &str) -> (String, bool) {
+    let mut result = String::new();
+    let mut rest = body;
+    let mut stripped = false;
+    while let Some(start) = rest.find(PYTHON_INTERPOLATION) {
+        if rest[..start].ends_with('\\') {
+            // an escaped backtick
+            result.push_str(&rest[..start + 1]);
+            rest = &rest[start + 1..];
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        stripped = true;
+        let code = &rest[start + PYTHON_INTERPOLATION.len()..];
+        rest = closing_backtick(code).map_or("", |end| &code[end + 1..]);
+    }
+    result.push_str(rest);
+    (result, stripped)
+}
+
+/// Parses an UltiSnips snippet file. `priority` applies to the snippets that
+/// follow it, and `extends` names filetypes whose snippets are offered too.
+/// Python code cannot run here: `global !p` blocks, snippet actions and
+/// `` `!p` `` interpolations are skipped with a warning, and so are regular
+/// expression triggers.
+pub fn parse_ultisnips(content: &str, filetype: &str) -> SnippetFile {
+    let mut file = SnippetFile {
+        filetype: Some(filetype.to_string()),
+        ..SnippetFile::default()
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    let mut priority = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end();
+        let number = i + 1;
+        i += 1;
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match keyword {
+            "snippet" => {
+                let start = i;
+                while i < lines.len() && lines[i].trim_end() != END_SNIPPET {
+                    i += 1;
+                }
+                if i == lines.len() {
+                    file.errors.push(format!("line {}: snippet without {}", number, END_SNIPPET));
+                    break;
+                }
+                let body = lines[start..i].join("\n");
+                i += 1;
+                let (trigger, description, options) = parse_header(rest);
+                if trigger.is_empty() {
+                    file.errors.push(format!("line {}: snippet without a trigger", number));
+                    continue;
+                }
+                if options.contains('r') {
+                    file.warnings.push(format!("line {}: skipped regular expression trigger {}", number, trigger));
+                    continue;
+                }
+                let (body, stripped) = strip_python(&body);
+                if stripped {
+                    file.warnings.push(format!("line {}: skipped python interpolation in snippet {}", number, trigger));
+                }
+                file.snippets.push(Snippet {
+                    name: trigger,
+                    snippet: body.replace('\t', "  "),
+                    filetype: filetype.to_string(),
+                    description,
+                    options,
+                    priority,
+                    syntax: SnippetSyntax::SnipMate,
+                });
+            }
+            "priority" => match rest.trim().parse() {
+                Ok(value) => priority = value,
+                Err(_) => file.errors.push(format!("line {}: invalid priority {}", number, rest.trim())),
+            },
+            "extends" => file.extends.extend(parse_extends(line).unwrap_or_default()),
+            "global" => {
+                while i < lines.len() && lines[i].trim_end() != END_GLOBAL {
+                    i += 1;
+                }
+                i += 1;
+                file.warnings.push(format!("line {}: skipped global {} block", number, rest.trim()));
+            }
+            _ if PYTHON_ACTIONS.contains(&keyword) => {
+                file.warnings.push(format!("line {}: skipped {} action", number, keyword));
+            }
+            // comments, blank lines and clearsnippets
+            _ => {}
+        }
+    }
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ultisnips() {
+        let content = "# comment
+extends c, cpp
+
+global !p
+def upper(s):
+\treturn s.upper()
+endglobal
+
+snippet main \"main function\" b
+int main(void) {
+\t$0
+}
+endsnippet
+
+priority 10
+snippet inc \"include\" w
+#include <${1:stdio}.h>
+endsnippet
+";
+        assert!(is_ultisnips(content));
+        let file = parse_ultisnips(content, "objc");
+        assert_eq!(vec!["c", "cpp"], file.extends);
+        assert!(file.errors.is_empty());
+        assert_eq!(vec!["line 4: skipped global !p block"], file.warnings);
+        assert_eq!(2, file.snippets.len());
+
+        let main = &file.snippets[0];
+        assert_eq!("main", main.name);
+        assert_eq!("int main(void) {\n  $0\n}", main.snippet);
+        assert_eq!(Some("main function".to_string()), main.description);
+        assert_eq!("b", main.options);
+        assert_eq!(0, main.priority);
+
+        let include = &file.snippets[1];
+        assert_eq!("#include <${1:stdio}.h>", include.snippet);
+        assert_eq!(10, include.priority);
+        assert!(include.matches("in", "x = "));
+    }
+
+    #[test]
+    fn test_parse_ultisnips_python() {
+        let content = "snippet date
+Today is `!p snip.rv = \"x\"`, not \\`!p\\`
+endsnippet
+context \"math()\"
+snippet ^^ \"superscript\" iA
+^{$1}
+endsnippet
+snippet \"(\\d+)x\" \"times\" r
+x
+endsnippet
+priority high
+snippet open
+unterminated";
+        assert!(!is_ultisnips("snippet for\n\tfor {}\n"));
+        let file = parse_ultisnips(content, "tex");
+        let names: Vec<&str> = file.snippets.iter().map(|snippet| snippet.name.as_str()).collect();
+        assert_eq!(vec!["date", "^^"], names);
+        assert_eq!("Today is , not \\`!p\\`", file.snippets[0].snippet);
+        assert_eq!(
+            vec![
+                "line 1: skipped python interpolation in snippet date",
+                "line 4: skipped context action",
+                "line 8: skipped regular expression trigger (\\d+)x",
+            ],
+            file.warnings
+        );
+        assert_eq!(vec!["line 11: invalid priority high", "line 12: snippet without endsnippet"], file.errors);
+    }
+}
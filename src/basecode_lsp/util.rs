@@ -1,20 +1,24 @@
+use super::config::Settings;
+use super::encoding::PositionEncoding;
+use super::expansion::*;
 use super::file::FileItem;
 use super::snippet::Snippet;
+use super::snippet::SnippetSyntax;
 use tower_lsp::lsp_types::*;
 
 pub fn valid_token_char(ch: char) -> bool {
     ch.is_alphanumeric() || ch == '_'
 }
 
-pub fn is_token(current: &Vec<char>, min_len: usize) -> bool {
+pub fn is_token(current: &[char], min_len: usize) -> bool {
     if current.len() < min_len {
         return false;
     }
-    if current.iter().all(|c| c.is_digit(10)) {
+    if current.iter().all(|c| c.is_ascii_digit()) {
         return false;
     }
     if let Some(first_char) = current.iter().next() {
-        if first_char.is_digit(10) {
+        if first_char.is_ascii_digit() {
             return false;
         }
     }
@@ -71,9 +75,77 @@ pub fn get_possible_current_word(current_line: &str, character: i32) -> Vec<Stri
     possible
 }
 
+pub fn get_word_range(current_line: &str, character: usize) -> Option<(usize, usize)> {
+    let line: Vec<char> = current_line.chars().collect();
+    let mut start = character.min(line.len());
+    while start > 0 && valid_token_char(line[start - 1]) {
+        start -= 1;
+    }
+    let mut end = character.min(line.len());
+    while end < line.len() && valid_token_char(line[end]) {
+        end += 1;
+    }
+    if start == end {
+        return None;
+    }
+    Some((start, end))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WordEdit {
+    pub insert: Range,
+    pub replace: Range,
+    pub insert_replace_support: bool,
+}
+
+impl WordEdit {
+    pub fn text_edit(&self, new_text: String) -> CompletionTextEdit {
+        if self.insert_replace_support {
+            CompletionTextEdit::InsertAndReplace(InsertReplaceEdit {
+                new_text,
+                insert: self.insert,
+                replace: self.replace,
+            })
+        } else {
+            CompletionTextEdit::Edit(TextEdit {
+                new_text,
+                range: self.replace,
+            })
+        }
+    }
+}
+
+/// Computes the ranges a completion replaces: the word prefix up to the cursor
+/// for insert mode, and the whole word under the cursor for replace mode.
+pub fn get_word_edit(
+    current_line: &str,
+    position: Position,
+    encoding: PositionEncoding,
+    insert_replace_support: bool,
+) -> WordEdit {
+    let character = encoding.char_index(current_line, position.character);
+    let prefix = get_word_prefix(current_line, character as i32);
+    let possible = get_possible_current_word(current_line, character as i32);
+    let start = character - prefix.chars().count();
+    let end = possible.last().map_or(character, |word| start + word.chars().count());
+    let column = |index: usize| encoding.char_column(current_line, index);
+    WordEdit {
+        insert: Range {
+            start: Position::new(position.line, column(start)),
+            end: Position::new(position.line, column(character)),
+        },
+        replace: Range {
+            start: Position::new(position.line, column(start)),
+            end: Position::new(position.line, column(end.max(character))),
+        },
+        insert_replace_support,
+    }
+}
+
 pub fn words_uri_pair_to_completion_items(
     words: Vec<(String, String)>,
-    suffixes: &Vec<String>,
+    suffixes: &[String],
+    word_edit: &WordEdit,
     completions: &mut Vec<CompletionItem>,
     kind: CompletionItemKind,
 ) {
@@ -85,6 +157,7 @@ pub fn words_uri_pair_to_completion_items(
             kind: Some(kind),
             sort_text: Some(item.0.to_string()),
             detail: Some(item.1.to_string()),
+            text_edit: Some(word_edit.text_edit(item.0.to_string())),
             ..CompletionItem::default()
         })
         .collect();
@@ -94,7 +167,8 @@ pub fn words_uri_pair_to_completion_items(
 pub fn words_to_completion_items(
     words: Vec<String>,
     source: String,
-    suffixes: &Vec<String>,
+    suffixes: &[String],
+    word_edit: &WordEdit,
     completions: &mut Vec<CompletionItem>,
     kind: CompletionItemKind,
 ) {
@@ -106,44 +180,102 @@ pub fn words_to_completion_items(
             kind: Some(kind),
             sort_text: Some(word.clone()),
             detail: Some(source.to_string()),
+            text_edit: Some(word_edit.text_edit(word.clone())),
             ..CompletionItem::default()
         })
         .collect();
     completions.extend(items);
 }
 
-pub fn snippets_to_completion_items(snippets: Vec<Snippet>, completions: &mut Vec<CompletionItem>) {
+/// Snippet items insert the snippet body, as an LSP snippet when the client
+/// supports them and as plain text otherwise.
+pub fn snippets_to_completion_items(
+    snippets: Vec<Snippet>,
+    word_edit: &WordEdit,
+    snippet_support: bool,
+    completions: &mut Vec<CompletionItem>,
+) {
     let items: Vec<CompletionItem> = snippets
         .into_iter()
-        .map(|snippet| CompletionItem {
-            label: snippet.name.clone(),
-            kind: Some(CompletionItemKind::SNIPPET),
-            documentation: Some(Documentation::String(snippet.markdown())),
-            ..CompletionItem::default()
+        .map(|snippet| {
+            let (body, format) = match (snippet.syntax, snippet_support) {
+                (SnippetSyntax::SnipMate, true) => (to_lsp_snippet(&snippet.snippet), InsertTextFormat::SNIPPET),
+                (SnippetSyntax::SnipMate, false) => (to_plain_text(&snippet.snippet), InsertTextFormat::PLAIN_TEXT),
+                (SnippetSyntax::Lsp, true) => (snippet.snippet.clone(), InsertTextFormat::SNIPPET),
+                (SnippetSyntax::Lsp, false) => (lsp_to_plain_text(&snippet.snippet), InsertTextFormat::PLAIN_TEXT),
+            };
+            CompletionItem {
+                label: snippet.name.clone(),
+                label_details: snippet.description.as_ref().map(|description| CompletionItemLabelDetails {
+                    detail: None,
+                    description: Some(description.clone()),
+                }),
+                kind: Some(CompletionItemKind::SNIPPET),
+                detail: snippet.description.clone(),
+                documentation: Some(Documentation::String(snippet.markdown())),
+                insert_text: Some(body.clone()),
+                insert_text_format: Some(format),
+                text_edit: Some(word_edit.text_edit(body)),
+                ..CompletionItem::default()
+            }
         })
         .collect();
     completions.extend(items);
 }
 
+/// Prefixes the sort text of `items`, so that the items of a source with a
+/// higher priority sort before the others.
+pub fn prioritize_completion_items(items: &mut [CompletionItem], sort_prefix: &str) {
+    for item in items.iter_mut() {
+        let sort_text = item.sort_text.take().unwrap_or_else(|| item.label.clone());
+        item.sort_text = Some(format!("{}{}", sort_prefix, sort_text));
+    }
+}
+
+/// Prioritizes the items of each source, given as the index each source's
+/// items end at, when the settings rank sources.
+pub fn prioritize_sources(items: &mut [CompletionItem], source_ends: &[(&str, usize)], settings: &Settings) {
+    if settings.source_priorities.is_empty() {
+        return;
+    }
+    let mut start = 0;
+    for (source, end) in source_ends.iter() {
+        prioritize_completion_items(&mut items[start..*end], &settings.priority_sort_prefix(source));
+        start = *end;
+    }
+}
+
+fn valid_filename_char(ch: char) -> bool {
+    !(ch.is_whitespace() || ch == '/' || ch == '\\' || ch == '"' || ch == '\'')
+}
+
 pub fn file_items_to_completion_items(
     file_items: Vec<FileItem>,
+    current_line: &str,
     params: &CompletionParams,
+    encoding: PositionEncoding,
     completions: &mut Vec<CompletionItem>,
 ) {
     let position = &params.text_document_position.position;
     let line = position.line;
     let mut items: Vec<CompletionItem> = Vec::new();
     for file_item in file_items.iter() {
+        let start = file_item.pos + 1;
+        let typed = current_line[start.min(current_line.len())..]
+            .chars()
+            .take_while(|&ch| valid_filename_char(ch))
+            .map(char::len_utf8)
+            .sum::<usize>();
         let text_edit = TextEdit {
             new_text: file_item.filename.clone(),
             range: Range {
                 start: Position {
                     line,
-                    character: file_item.pos as u32 + 1,
+                    character: encoding.column(current_line, start),
                 },
                 end: Position {
                     line,
-                    character: (file_item.pos + file_item.filename.len()) as u32,
+                    character: encoding.column(current_line, start + typed),
                 },
             },
         };
@@ -166,6 +298,60 @@ pub fn file_items_to_completion_items(
 mod test {
     use super::*;
 
+    #[test]
+    fn test_snippets_to_completion_items() {
+        let snippets = vec![Snippet {
+            name: "fn".to_string(),
+            snippet: "fn ${1:name}() {\n  $0\n}".to_string(),
+            filetype: "rust".to_string(),
+            description: Some("function".to_string()),
+            options: String::new(),
+            priority: 0,
+            syntax: SnippetSyntax::SnipMate,
+        }];
+        let word_edit = get_word_edit("fn", Position::new(0, 2), PositionEncoding::default(), false);
+        let mut completions = Vec::new();
+        snippets_to_completion_items(snippets.clone(), &word_edit, true, &mut completions);
+        assert_eq!(Some("fn ${1:name}() {\n  $0\n\\}".to_string()), completions[0].insert_text);
+        assert_eq!(Some(InsertTextFormat::SNIPPET), completions[0].insert_text_format);
+        assert_eq!(Some("function".to_string()), completions[0].detail);
+
+        let mut completions = Vec::new();
+        snippets_to_completion_items(snippets.clone(), &word_edit, false, &mut completions);
+        assert_eq!(Some("fn name() {\n  \n}".to_string()), completions[0].insert_text);
+        assert_eq!(Some(InsertTextFormat::PLAIN_TEXT), completions[0].insert_text_format);
+
+        let snippets = vec![Snippet {
+            snippet: "fn ${1:name}() {} // ${TM_FILENAME_BASE}".to_string(),
+            syntax: SnippetSyntax::Lsp,
+            ..snippets[0].clone()
+        }];
+        let mut completions = Vec::new();
+        snippets_to_completion_items(snippets.clone(), &word_edit, true, &mut completions);
+        assert_eq!(Some("fn ${1:name}() {} // ${TM_FILENAME_BASE}".to_string()), completions[0].insert_text);
+        let mut completions = Vec::new();
+        snippets_to_completion_items(snippets, &word_edit, false, &mut completions);
+        assert_eq!(Some("fn name() {} // ".to_string()), completions[0].insert_text);
+    }
+
+    #[test]
+    fn test_prioritize_completion_items() {
+        let mut items = vec![
+            CompletionItem {
+                label: "apple".to_string(),
+                sort_text: Some("apple".to_string()),
+                ..CompletionItem::default()
+            },
+            CompletionItem {
+                label: "banana".to_string(),
+                ..CompletionItem::default()
+            },
+        ];
+        prioritize_completion_items(&mut items, "49999");
+        assert_eq!(Some("49999apple".to_string()), items[0].sort_text);
+        assert_eq!(Some("49999banana".to_string()), items[1].sort_text);
+    }
+
     #[test]
     fn test_get_word_prefix() {
         let prefix = get_word_prefix("   ios::sync_with_stdio", 24);
@@ -211,6 +397,89 @@ mod test {
         assert_eq!(vec!["m", "ma", "max"], suffixes);
     }
 
+    #[test]
+    fn test_get_word_range() {
+        assert_eq!(Some((8, 12)), get_word_range("   ios::sync", 8));
+        assert_eq!(Some((8, 12)), get_word_range("   ios::sync", 10));
+        assert_eq!(Some((8, 12)), get_word_range("   ios::sync", 12));
+        assert_eq!(Some((3, 6)), get_word_range("   ios::sync", 6));
+        assert_eq!(None, get_word_range("   ios::sync", 7));
+        assert_eq!(None, get_word_range("", 0));
+    }
+
+    #[test]
+    fn test_get_word_edit() {
+        let line = "   int best = numeric_limits<int>::max();";
+        let word_edit = get_word_edit(line, Position::new(3, 17), PositionEncoding::Utf16, true);
+        assert_eq!(Range::new(Position::new(3, 14), Position::new(3, 17)), word_edit.insert);
+        assert_eq!(Range::new(Position::new(3, 14), Position::new(3, 28)), word_edit.replace);
+
+        let word_edit = get_word_edit(line, Position::new(0, 38), PositionEncoding::Utf16, false);
+        assert_eq!(Range::new(Position::new(0, 35), Position::new(0, 38)), word_edit.insert);
+        assert_eq!(Range::new(Position::new(0, 35), Position::new(0, 38)), word_edit.replace);
+
+        let word_edit = get_word_edit("ab", Position::new(0, 10), PositionEncoding::Utf16, true);
+        assert_eq!(Range::new(Position::new(0, 0), Position::new(0, 2)), word_edit.insert);
+        assert_eq!(word_edit.insert, word_edit.replace);
+
+        let line = "// 😀 héllo_wörld";
+        let word_edit = get_word_edit(line, Position::new(0, 9), PositionEncoding::Utf16, true);
+        assert_eq!(Range::new(Position::new(0, 6), Position::new(0, 9)), word_edit.insert);
+        assert_eq!(Range::new(Position::new(0, 6), Position::new(0, 17)), word_edit.replace);
+
+        let word_edit = get_word_edit(line, Position::new(0, 12), PositionEncoding::Utf8, true);
+        assert_eq!(Range::new(Position::new(0, 8), Position::new(0, 12)), word_edit.insert);
+        assert_eq!(Range::new(Position::new(0, 8), Position::new(0, 21)), word_edit.replace);
+    }
+
+    #[test]
+    fn test_file_items_to_completion_items() {
+        let line = "中文 \"src/ma\"";
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier::new(Url::parse("file:///a.txt").unwrap()),
+                position: Position::new(1, 10),
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: None,
+        };
+        let file_items = vec![FileItem {
+            filename: "main.rs".to_string(),
+            pos: line.find('/').unwrap(),
+            is_dir: false,
+        }];
+        let mut completions = Vec::new();
+        file_items_to_completion_items(file_items, line, &params, PositionEncoding::Utf16, &mut completions);
+        assert_eq!(
+            Some(CompletionTextEdit::Edit(TextEdit {
+                new_text: "main.rs".to_string(),
+                range: Range::new(Position::new(1, 8), Position::new(1, 10)),
+            })),
+            completions[0].text_edit
+        );
+    }
+
+    #[test]
+    fn test_words_to_completion_items() {
+        let word_edit = get_word_edit("let numb = 1;", Position::new(0, 6), PositionEncoding::Utf16, true);
+        let suffixes = get_possible_current_word("let numb = 1;", 6);
+        let mut completions = Vec::new();
+        let words = vec!["number".to_string(), "numb".to_string()];
+        words_to_completion_items(words, "tmux".to_string(), &suffixes, &word_edit, &mut completions, CompletionItemKind::TEXT);
+        assert_eq!(1, completions.len());
+        // clients filter by the label, which is the inserted word
+        assert_eq!(None, completions[0].filter_text);
+        assert_eq!(
+            Some(CompletionTextEdit::InsertAndReplace(InsertReplaceEdit {
+                new_text: "number".to_string(),
+                insert: Range::new(Position::new(0, 4), Position::new(0, 6)),
+                replace: Range::new(Position::new(0, 4), Position::new(0, 8)),
+            })),
+            completions[0].text_edit
+        );
+    }
+
     #[test]
     fn test_process_token() {
         let tokens = process_token("   aho_corasick(root.get())", 2);
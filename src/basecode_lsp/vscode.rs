@@ -0,0 +1,192 @@
+use super::snippet::Snippet;
+use super::snippet::SnippetSyntax;
+use super::snippet::GLOBAL_FILETYPE;
+use serde_json::Value;
+
+/// VSCode language ids that differ from our snippet filetypes.
+const VSCODE_LANGUAGES: [(&str, &str); 3] = [("shellscript", "sh"), ("typescript", "javascript"), ("javascriptreact", "javascript")];
+
+fn filetype_of(language: &str) -> String {
+    VSCODE_LANGUAGES
+        .iter()
+        .find(|(vscode, _)| *vscode == language)
+        .map_or(language.to_string(), |(_, filetype)| filetype.to_string())
+}
+
+/// Removes `//` and `/* */` comments and trailing commas, which VSCode
+/// accepts in snippet files but JSON does not.
+fn strip_jsonc(content: &str) -> String {
+    let mut result = String::new();
+    let mut chars = content.chars().peekable();
+    let mut in_string = false;
+    while let Some(ch) = chars.next() {
+        if in_string {
+            result.push(ch);
+            match ch {
+                '\\' => result.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (ch, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                result.push(ch);
+            }
+            ('/', Some('/')) => {
+                while chars.peek().is_some_and(|&next| next != '\n') {
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    // keep line numbers in parse errors right
+                    if next == '\n' {
+                        result.push(next);
+                    }
+                    previous = next;
+                }
+            }
+            (']' | '}', _) => {
+                let trimmed = result.trim_end().len();
+                if result[..trimmed].ends_with(',') {
+                    result.truncate(trimmed - 1);
+                }
+                result.push(ch);
+            }
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::String(s)) => vec![s.clone()],
+        Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether a `.json` file holds VSCode snippets: an object of snippets that
+/// each have a `prefix` or a `body`. Snippet folders may also contain files
+/// such as `package.json`, which are not ours to report errors for.
+pub fn is_vscode_snippets(content: &str) -> bool {
+    match serde_json::from_str::<Value>(&strip_jsonc(content)) {
+        Ok(Value::Object(entries)) => {
+            !entries.is_empty()
+                && entries
+                    .values()
+                    .all(|entry| entry.as_object().is_some_and(|entry| entry.contains_key("prefix") || entry.contains_key("body")))
+        }
+        _ => false,
+    }
+}
+
+/// Parses a VSCode snippet file. A language file such as `rust.json` passes
+/// its `filetype`; in `.code-snippets` files the `scope` of each snippet
+/// decides, and snippets without a scope apply to every filetype.
+pub fn parse_vscode_snippets(content: &str, filetype: Option<&str>) -> (Vec<Snippet>, Vec<String>) {
+    let mut snippets = Vec::new();
+    let mut errors = Vec::new();
+    let entries = match serde_json::from_str::<Value>(&strip_jsonc(content)) {
+        Ok(Value::Object(entries)) => entries,
+        Ok(_) => return (snippets, vec!["expected an object of snippets".to_string()]),
+        Err(e) => return (snippets, vec![format!("line {}: {}", e.line(), e)]),
+    };
+    for (key, entry) in entries.iter() {
+        let prefixes = strings(entry.get("prefix"));
+        let body = strings(entry.get("body"));
+        if prefixes.is_empty() || body.is_empty() {
+            errors.push(format!("snippet \"{}\" needs a prefix and a body", key));
+            continue;
+        }
+        let filetypes = match filetype {
+            Some(filetype) => vec![filetype.to_string()],
+            None => {
+                let scopes = entry.get("scope").and_then(Value::as_str).unwrap_or_default();
+                let filetypes: Vec<String> = scopes.split(',').map(str::trim).filter(|s| !s.is_empty()).map(filetype_of).collect();
+                if filetypes.is_empty() {
+                    vec![GLOBAL_FILETYPE.to_string()]
+                } else {
+                    filetypes
+                }
+            }
+        };
+        let description = entry.get("description").and_then(Value::as_str).unwrap_or(key);
+        // bodies are already in the LSP snippet syntax
+        let body = body.join("\n");
+        for filetype in filetypes.iter() {
+            for prefix in prefixes.iter() {
+                snippets.push(Snippet {
+                    name: prefix.clone(),
+                    snippet: body.clone(),
+                    filetype: filetype.clone(),
+                    description: Some(description.to_string()),
+                    options: String::new(),
+                    priority: 0,
+                    syntax: SnippetSyntax::Lsp,
+                });
+            }
+        }
+    }
+    (snippets, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vscode_snippets() {
+        let content = r#"{
+            // loops
+            "For Loop": {
+                "prefix": ["for", "fori"],
+                "body": ["for (let ${1:i} = 0; $1 < ${2:n}; $1++) {", "\t$0", "}"],
+                "description": "For loop",
+            },
+            "Log": {"prefix": "log", "body": "console.log($1);"},
+            "Broken": {"body": "no prefix"}
+        }"#;
+        let (snippets, errors) = parse_vscode_snippets(content, Some("javascript"));
+        let names: Vec<&str> = snippets.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(vec!["for", "fori", "log"], names);
+        assert_eq!("for (let ${1:i} = 0; $1 < ${2:n}; $1++) {\n\t$0\n}", snippets[0].snippet);
+        assert_eq!(SnippetSyntax::Lsp, snippets[0].syntax);
+        assert_eq!(Some("For loop".to_string()), snippets[0].description);
+        assert_eq!(Some("Log".to_string()), snippets[2].description);
+        assert_eq!(vec!["snippet \"Broken\" needs a prefix and a body"], errors);
+    }
+
+    #[test]
+    fn test_parse_code_snippets_scope() {
+        let content = r##"{
+            "Shebang": {"prefix": "sb", "body": "#!/bin/sh", "scope": "shellscript, python"},
+            "Date": {"prefix": "date", "body": "${CURRENT_YEAR:2024}"}
+        }"##;
+        let (snippets, errors) = parse_vscode_snippets(content, None);
+        assert!(errors.is_empty());
+        let filetypes: Vec<&str> = snippets.iter().map(|s| s.filetype.as_str()).collect();
+        assert_eq!(vec![GLOBAL_FILETYPE, "sh", "python"], filetypes);
+        assert_eq!("${CURRENT_YEAR:2024}", snippets[0].snippet);
+
+        let (_, errors) = parse_vscode_snippets("{\n\"a\": }", None);
+        assert!(errors[0].starts_with("line 2:"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn test_is_vscode_snippets() {
+        assert!(is_vscode_snippets(r#"{"Log": {"prefix": "log", "body": "console.log($1);"}, "Broken": {"body": "x"}}"#));
+        assert!(!is_vscode_snippets(r#"{"name": "app", "version": "1.0.0", "scripts": {"test": "jest"}}"#));
+        assert!(!is_vscode_snippets(r#"{"compilerOptions": {"strict": true}}"#));
+        assert!(!is_vscode_snippets("{}"));
+        assert!(!is_vscode_snippets("[1, 2]"));
+    }
+}
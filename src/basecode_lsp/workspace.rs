@@ -0,0 +1,122 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+const MAX_WORKSPACE_FILES: usize = 5000;
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+const IGNORED_FOLDERS: [&str; 6] = ["target", "node_modules", "build", "dist", "out", "__pycache__"];
+
+fn is_ignored(path: &Path, is_dir: bool) -> bool {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.starts_with('.') || (is_dir && IGNORED_FOLDERS.contains(&name)),
+        None => true,
+    }
+}
+
+/// Lists the files under `root`, skipping hidden and build folders. Symlinked
+/// folders are not followed: a link to an ancestor would never end the walk.
+pub fn list_workspace_files(root: &Path) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(folder) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&folder) else {
+            continue;
+        };
+        let mut paths: Vec<(PathBuf, bool)> = entries
+            .flatten()
+            .filter_map(|entry| Some((entry.path(), entry.file_type().ok()?.is_dir())))
+            .collect();
+        paths.sort();
+        for (path, is_dir) in paths.into_iter().rev() {
+            if is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                stack.push(path);
+            } else if fs::metadata(&path).is_ok_and(|m| m.is_file() && m.len() <= MAX_FILE_SIZE) {
+                result.push(path);
+                if result.len() >= MAX_WORKSPACE_FILES {
+                    return result;
+                }
+            }
+        }
+    }
+    result
+}
+
+pub fn read_text_file(path: &Path) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    if content.contains('\0') {
+        return None;
+    }
+    Some(content)
+}
+
+/// The innermost of `folders` that contains `path`.
+pub fn containing_folder<'a>(folders: &'a [PathBuf], path: &Path) -> Option<&'a PathBuf> {
+    folders
+        .iter()
+        .filter(|folder| path.starts_with(folder))
+        .max_by_key(|folder| folder.components().count())
+}
+
+/// The nearest ancestor of `path` that is the root of a git work tree.
+pub fn find_git_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors().skip(1).find(|folder| folder.join(".git").exists()).map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_containing_folder() {
+        let folders = vec![PathBuf::from("/work"), PathBuf::from("/work/lib"), PathBuf::from("/other")];
+        assert_eq!(Some(&folders[1]), containing_folder(&folders, Path::new("/work/lib/src/a.rs")));
+        assert_eq!(Some(&folders[0]), containing_folder(&folders, Path::new("/work/library/a.rs")));
+        assert_eq!(None, containing_folder(&folders, Path::new("/tmp/a.rs")));
+    }
+
+    #[test]
+    fn test_find_git_root() {
+        let root = std::env::temp_dir().join("basecode-lsp-git-root-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        assert_eq!(Some(root.clone()), find_git_root(&root.join("src/main.rs")));
+        assert_eq!(Some(root.clone()), find_git_root(&root.join("src/nested/lib.rs")));
+        // a .git file, as in submodules and worktrees, marks a root as well
+        fs::write(root.join("src/nested/.git"), "gitdir: ../../.git/modules/nested").unwrap();
+        assert_eq!(Some(root.join("src/nested")), find_git_root(&root.join("src/nested/lib.rs")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_list_workspace_files_symlink_cycle() {
+        let root = std::env::temp_dir().join("basecode-lsp-symlink-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("src/loop")).unwrap();
+        assert_eq!(vec![root.join("src/main.rs")], list_workspace_files(&root));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_list_workspace_files() {
+        let files = list_workspace_files(Path::new("./src"));
+        assert!(files.iter().any(|p| p.ends_with("main.rs")));
+        assert!(files.iter().any(|p| p.ends_with("basecode_lsp/backend.rs")));
+
+        let files = list_workspace_files(Path::new("./"));
+        assert!(files.iter().any(|p| p.ends_with("Cargo.toml")));
+        assert!(!files.iter().any(|p| p.starts_with("./.git")));
+        assert!(!files.iter().any(|p| p.starts_with("./target")));
+    }
+
+    #[test]
+    fn test_read_text_file() {
+        assert!(read_text_file(Path::new("./Cargo.toml")).is_some());
+        assert!(read_text_file(Path::new("doesnt_exist")).is_none());
+    }
+}
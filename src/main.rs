@@ -1,11 +1,23 @@
 mod basecode_lsp;
 
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 
 use basecode_lsp::backend::*;
+use basecode_lsp::daemon::*;
+use basecode_lsp::inline::InlineCompletionService;
+use basecode_lsp::inline::INLINE_COMPLETION_METHOD;
+use basecode_lsp::marker::LIST_MARKERS_METHOD;
+use basecode_lsp::progress::WORK_DONE_PROGRESS_CANCEL_METHOD;
+use basecode_lsp::transport::*;
 use clap::Parser;
 use simple_log::error;
+use simple_log::info;
 use simple_log::LogConfigBuilder;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::sync::Notify;
 use tower_lsp::{LspService, Server};
 
 fn setup_debug_logging() {
@@ -15,7 +27,55 @@ fn setup_debug_logging() {
         let config = LogConfigBuilder::builder().path(log_path).build();
         if let Err(_e) = simple_log::new(config) {
             error!("fail to setup log {}", log_path);
-            return;
+        }
+    }
+}
+
+/// Runs one LSP session over `input` and `output`.
+async fn serve<I, O>(input: I, output: O, args: LspArgs, shared: SharedState)
+where
+    I: AsyncRead + Unpin,
+    O: AsyncWrite,
+{
+    let (service, socket) = LspService::build(|client| Backend::new(client, args, shared))
+        .custom_method(INLINE_COMPLETION_METHOD, Backend::inline_completion)
+        .custom_method(LIST_MARKERS_METHOD, Backend::list_markers)
+        .custom_method(WORK_DONE_PROGRESS_CANCEL_METHOD, Backend::cancel_progress)
+        .finish();
+    let closed = Arc::new(Notify::new());
+    let input = ClosingReader::new(input, closed.clone());
+    tokio::select! {
+        _ = Server::new(input, output, socket).serve(InlineCompletionService::new(service)) => {}
+        _ = closed.notified() => {}
+    }
+}
+
+/// Serves every connection to `listener`. With `--shared` the sessions share
+/// their state and the server exits once it has been idle for `--idle-timeout`.
+async fn serve_connections(listener: Listener, args: LspArgs) {
+    let shared = SharedState::daemon();
+    let idle_timeout = Duration::from_secs(args.idle_timeout);
+    loop {
+        let idle = args.shared && shared.session_count() == 0;
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((input, output)) => {
+                    info!("accepted connection on {}", listener.address());
+                    let state = if args.shared { shared.clone() } else { SharedState::new() };
+                    let session = shared.session();
+                    let args = args.clone();
+                    tokio::spawn(async move {
+                        serve(input, output, args, state).await;
+                        drop(session);
+                    });
+                }
+                Err(e) => error!("fail to accept connection: {}", e),
+            },
+            _ = shared.idle(), if args.shared => {}
+            _ = tokio::time::sleep(idle_timeout), if idle => {
+                info!("no connections for {} seconds, exiting", args.idle_timeout);
+                break;
+            }
         }
     }
 }
@@ -28,9 +88,40 @@ async fn main() {
         setup_debug_logging();
     }
 
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
-    let backend = Backend::new(args);
-    let (service, socket) = LspService::new(|_| backend);
-    Server::new(stdin, stdout, socket).serve(service).await;
+    if args.daemon {
+        let socket = match daemon_socket_path() {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("fail to prepare daemon socket: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let result = match connect_daemon(&socket).await {
+            Ok(stream) => run_shim(stream).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("fail to connect to daemon at {}: {}", socket.display(), e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let listen = args.listen.clone().unwrap_or(Listen::Stdio);
+    if listen == Listen::Stdio {
+        serve(tokio::io::stdin(), tokio::io::stdout(), args, SharedState::new()).await;
+        return;
+    }
+    let listener = match Listener::bind(&listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("fail to listen on {:?}: {}", listen, e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("listening on {}", listener.address());
+    serve_connections(listener, args).await;
+    if let Listen::Unix(path) = listen {
+        let _ = std::fs::remove_file(path);
+    }
 }